@@ -0,0 +1,105 @@
+// The executor's injector against crossbeam's channels, as a ready queue:
+// `cargo bench --bench injector`.
+#![allow(dead_code)]
+
+#[path = "../src/simple_excutor/injector.rs"]
+mod injector;
+
+use injector::Injector;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+const VALUES: usize = 1_000_000;
+const ROUNDS: usize = 5;
+
+// The bound the executor's ready queues have by default.
+const CAPACITY: usize = 1000;
+
+// The two ends of a queue, the way the executor uses them: pushes fail
+// when it is full, pops come back empty instead of blocking.
+trait Queue: Sync {
+    fn push(&self, value: usize) -> bool;
+    fn pop(&self) -> Option<usize>;
+}
+
+impl Queue for Injector<usize> {
+    fn push(&self, value: usize) -> bool {
+        Injector::push(self, value).is_ok()
+    }
+
+    fn pop(&self) -> Option<usize> {
+        Injector::pop(self)
+    }
+}
+
+type Channel = (
+    crossbeam_channel::Sender<usize>,
+    crossbeam_channel::Receiver<usize>,
+);
+
+impl Queue for Channel {
+    fn push(&self, value: usize) -> bool {
+        self.0.try_send(value).is_ok()
+    }
+
+    fn pop(&self) -> Option<usize> {
+        self.1.try_recv().ok()
+    }
+}
+
+// Time to get `VALUES` values through, split across the producers and the
+// consumers. Best of the rounds, per value.
+fn run<Q: Queue>(new_queue: impl Fn() -> Q, producers: usize, consumers: usize) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let queue = new_queue();
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..producers {
+                s.spawn(|| {
+                    for i in 0..VALUES / producers {
+                        while !queue.push(i) {
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+            for _ in 0..consumers {
+                s.spawn(|| {
+                    let mut popped = 0;
+                    while popped < VALUES / consumers {
+                        match queue.pop() {
+                            Some(value) => {
+                                std::hint::black_box(value);
+                                popped += 1;
+                            }
+                            None => thread::yield_now(),
+                        }
+                    }
+                });
+            }
+        });
+        best = best.min(start.elapsed());
+    }
+    best / VALUES as u32
+}
+
+fn main() {
+    for (producers, consumers) in [(1, 1), (4, 4)] {
+        println!("{producers} producers, {consumers} consumers");
+        let injector = run(|| Injector::new(None), producers, consumers);
+        let bounded = run(|| Injector::new(Some(CAPACITY)), producers, consumers);
+        let unbounded = run(crossbeam_channel::unbounded, producers, consumers);
+        let channel = run(
+            || crossbeam_channel::bounded(CAPACITY),
+            producers,
+            consumers,
+        );
+        println!("  injector:                  {injector:?}");
+        println!("  injector, bounded:         {bounded:?}");
+        println!("  crossbeam unbounded:       {unbounded:?}");
+        println!("  crossbeam bounded:         {channel:?}");
+    }
+}
@@ -0,0 +1,63 @@
+// Spawn cost of the work-stealing executor: `cargo bench --bench spawn`.
+// The executor lives in the binary, so pull its modules in directly.
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src"]
+mod src {
+    pub mod simple_excutor;
+    pub mod simple_future;
+}
+use src::{simple_excutor, simple_future};
+
+use simple_excutor::ExecutorBuilder;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+// Counts heap allocations, to see how many a spawn takes.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const TASKS: u32 = 1_000_000;
+const ROUNDS: usize = 5;
+
+fn main() {
+    let mut spawn = Duration::MAX;
+    let mut total = Duration::MAX;
+    let mut allocations = 0;
+    for _ in 0..ROUNDS {
+        let (executor, spawner) = ExecutorBuilder::new().unbounded().build();
+        let allocated = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        for i in 0..TASKS {
+            drop(spawner.spawn(async move { std::hint::black_box(i) }));
+        }
+        let spawned = start.elapsed();
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocated;
+        drop(spawner);
+        executor.run();
+        spawn = spawn.min(spawned);
+        total = total.min(start.elapsed());
+    }
+    // Best of the rounds, per task.
+    println!("spawn:        {:?}", spawn / TASKS);
+    println!("spawn + run:  {:?}", total / TASKS);
+    println!("allocations:  {:.2}", allocations as f64 / TASKS as f64);
+}
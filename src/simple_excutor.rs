@@ -1,62 +1,970 @@
-use crossbeam_channel::{bounded, Receiver, Sender};
-use futures::{
-    future::{BoxFuture, FutureExt},
-    task::ArcWake,
-};
 use std::{
+    any::Any,
+    fmt,
     future::Future,
-    sync::{Arc, Mutex},
+    panic::{catch_unwind, AssertUnwindSafe, Location},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
 };
 
+mod atomic_waker;
+mod block_on;
+mod blocking;
+mod coop;
+mod dump;
+mod hooks;
+mod injector;
+mod join_handle;
+mod join_set;
+mod local;
+mod metrics;
+mod park;
+#[cfg(target_os = "linux")]
+mod per_core;
+mod priority;
+mod registry;
+mod scope;
+mod shutdown;
+mod sim;
+mod state;
+mod task_ref;
+mod timer;
+mod worker;
+
+pub(crate) use atomic_waker::AtomicWaker;
+pub use block_on::block_on;
+pub use coop::{poll_proceed, yield_now};
+pub use dump::{TaskDump, TaskState};
+pub use hooks::{TaskHooks, TaskInfo, TaskOutcome};
+use join_handle::Panicked;
+pub use join_handle::{AbortHandle, JoinError, JoinHandle};
+pub use join_set::JoinSet;
+pub use local::{spawn_local, LocalExecutor};
+pub use metrics::{Histogram, MetricsSnapshot};
+#[cfg(target_os = "linux")]
+pub use per_core::{CoreHandle, ThreadPerCore};
+pub use priority::Priority;
+pub use scope::{Scope, ScopedJoinHandle};
+pub use shutdown::ShutdownReport;
+pub use sim::Simulation;
+pub(crate) use sim::{add_virtual_timer, cancel_virtual_timer};
+pub(crate) use timer::{add_timer, cancel_timer};
+
+use injector::{Backoff, Injector};
+use task_ref::{TaskRef, WeakTaskRef};
+
+type PanicHook = dyn Fn(&(dyn Any + Send)) + Send + Sync + 'static;
+
 pub struct Executor {
-    ready_queue: Receiver<Arc<Task>>,
+    shared: Arc<Shared>,
 }
 
-#[derive(Clone)]
 pub struct Spawner {
-    task_sender: Sender<Arc<Task>>,
+    shared: Arc<Shared>,
+}
+
+// State shared by the executor, its spawners and its tasks.
+struct Shared {
+    // The global ready queues, one per priority class, highest first. Fed
+    // by `Spawner` and by wakes from other threads.
+    injectors: [Injector<TaskRef>; Priority::COUNT],
+    // Set once shutdown starts, or the executor is dropped. No new tasks
+    // are accepted after that.
+    closed: AtomicBool,
+    // Set once the executor is dropped. Woken tasks are dropped instead of
+    // queued after that.
+    dropped: AtomicBool,
+    // Number of `Spawner`s, including the ones held by tasks. Workers stop
+    // once it drops to zero.
+    spawners: AtomicUsize,
+    parker: park::Parker,
+    // Every task that has been spawned and has not finished yet.
+    tasks: registry::Registry,
+    next_task_id: AtomicUsize,
+    completed: AtomicUsize,
+    cancelled: AtomicUsize,
+    panicked: AtomicUsize,
+    panic_hook: Option<Arc<PanicHook>>,
+    hooks: Option<Box<dyn TaskHooks>>,
+    blocking: Arc<blocking::BlockingPool>,
+    metrics: metrics::Metrics,
+    // What `Task::last_poll` counts from.
+    started: Instant,
+}
+
+// Spawns a single task with the given settings, see `Spawner::builder`.
+pub struct TaskBuilder<'a> {
+    spawner: &'a Spawner,
+    name: Option<String>,
+    priority: Priority,
 }
 
 struct Task {
-    future: Mutex<Option<BoxFuture<'static, ()>>>,
-    task_sender: Sender<Arc<Task>>,
+    id: usize,
+    name: Option<String>,
+    // Where the task was spawned.
+    location: &'static Location<'static>,
+    priority: Priority,
+    state: state::State,
+    spawner: Spawner,
+    // Set by `AbortHandle::abort`, the future is dropped on the next poll.
+    aborted: AtomicBool,
+    polls: AtomicUsize,
+    // Nanoseconds since `Shared::started`, `u64::MAX` if never polled.
+    last_poll: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    // The ready queue is at capacity.
+    Full,
+    // The executor has been shut down or dropped.
+    Shutdown,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::Full => f.write_str("ready queue is full"),
+            SpawnError::Shutdown => f.write_str("executor has shut down"),
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {}
+
+pub struct ExecutorBuilder {
+    // `None` means unbounded.
+    capacity: Option<usize>,
+    panic_hook: Option<Arc<PanicHook>>,
+    hooks: Option<Box<dyn TaskHooks>>,
+    blocking: blocking::Config,
+}
+
+impl ExecutorBuilder {
+    pub fn new() -> Self {
+        Self {
+            capacity: Some(1000),
+            panic_hook: None,
+            hooks: None,
+            blocking: blocking::Config::default(),
+        }
+    }
+
+    // Bound the ready queue of each priority class to `capacity` spawned tasks.
+    // Wakes of tasks that are already spawned do not count against it.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn unbounded(mut self) -> Self {
+        self.capacity = None;
+        self
+    }
+
+    // Called with the payload of every task that panics, before the payload
+    // is handed to the task's join handle.
+    pub fn panic_hook(mut self, hook: impl Fn(&(dyn Any + Send)) + Send + Sync + 'static) -> Self {
+        self.panic_hook = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn hooks(mut self, hooks: impl TaskHooks) -> Self {
+        self.hooks = Some(Box::new(hooks));
+        self
+    }
+
+    // Most threads `spawn_blocking` runs at once, 512 by default. Jobs
+    // beyond that wait for a thread to free up.
+    pub fn max_blocking_threads(mut self, max_threads: usize) -> Self {
+        assert!(max_threads > 0, "need at least one blocking thread");
+        self.blocking.max_threads = max_threads;
+        self
+    }
+
+    // How long an idle blocking thread waits for more work before it exits,
+    // 10 seconds by default.
+    pub fn blocking_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.blocking.keep_alive = keep_alive;
+        self
+    }
+
+    // Blocking threads are named `{name}-{n}`.
+    pub fn blocking_thread_name(mut self, name: impl Into<String>) -> Self {
+        self.blocking.thread_name = name.into();
+        self
+    }
+
+    pub fn build(self) -> (Executor, Spawner) {
+        let shared = Arc::new(Shared {
+            injectors: std::array::from_fn(|_| Injector::new(self.capacity)),
+            closed: AtomicBool::new(false),
+            dropped: AtomicBool::new(false),
+            spawners: AtomicUsize::new(1),
+            parker: park::Parker::default(),
+            tasks: registry::Registry::default(),
+            next_task_id: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            cancelled: AtomicUsize::new(0),
+            panicked: AtomicUsize::new(0),
+            panic_hook: self.panic_hook,
+            hooks: self.hooks,
+            blocking: Arc::new(blocking::BlockingPool::new(self.blocking)),
+            metrics: metrics::Metrics::default(),
+            started: Instant::now(),
+        });
+        (
+            Executor {
+                shared: shared.clone(),
+            },
+            Spawner { shared },
+        )
+    }
+}
+
+impl Default for ExecutorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub fn new_executor_and_spawner() -> (Executor, Spawner) {
-    let (task_sender, ready_queue) = bounded(1000);
-    (Executor { ready_queue }, Spawner { task_sender })
+    ExecutorBuilder::new().build()
 }
 
 impl Spawner {
-    pub fn spawn(&self, future: impl Future<Output = ()> + 'static + Send) {
-        let future = future.boxed();
-        let task = Arc::new(Task {
-            future: Mutex::new(Some(future)),
-            task_sender: self.task_sender.clone(),
+    // Spawn a task, waiting for room if the ready queue is full.
+    // On one of the executor's own worker threads it never waits: a full
+    // queue spills into that worker's local queue instead.
+    // Panics if the executor has been shut down or dropped, see `try_spawn`.
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static + Send,
+        F::Output: Send + 'static,
+    {
+        self.builder().spawn(future)
+    }
+
+    // Like `spawn`, for a task of the given class. Every time it wakes, the
+    // task is queued behind the ready tasks of its own class only.
+    #[track_caller]
+    pub fn spawn_with_priority<F>(&self, priority: Priority, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static + Send,
+        F::Output: Send + 'static,
+    {
+        self.builder().priority(priority).spawn(future)
+    }
+
+    // Spawn a task without blocking or panicking.
+    #[track_caller]
+    pub fn try_spawn<F>(&self, future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+    where
+        F: Future + 'static + Send,
+        F::Output: Send + 'static,
+    {
+        self.builder().try_spawn(future)
+    }
+
+    // Configure a task before spawning it.
+    pub fn builder(&self) -> TaskBuilder<'_> {
+        TaskBuilder {
+            spawner: self,
+            name: None,
+            priority: Priority::Normal,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+}
+
+impl Clone for Spawner {
+    fn clone(&self) -> Self {
+        self.shared.spawners.fetch_add(1, Ordering::Relaxed);
+        Spawner {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for Spawner {
+    fn drop(&mut self) {
+        if self.shared.spawners.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Let the sleeping workers see they are done.
+            self.shared.parker.notify_all();
+        }
+    }
+}
+
+impl TaskBuilder<'_> {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    // See `Spawner::spawn`.
+    #[track_caller]
+    pub fn spawn<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static + Send,
+        F::Output: Send + 'static,
+    {
+        // Safety: nothing borrowed.
+        unsafe { self.spawn_unchecked(future, ()) }
+    }
+
+    // Spawn a future that may borrow. `keep` is dropped right after the
+    // future, whenever that happens.
+    // Safety: the future must be dropped before anything it borrows goes away.
+    #[track_caller]
+    unsafe fn spawn_unchecked<'a, F, K>(self, future: F, keep: K) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'a,
+        F::Output: Send + 'a,
+        K: Send + 'a,
+    {
+        let spawner = self.spawner;
+        assert!(!spawner.is_closed(), "{}", SpawnError::Shutdown);
+        let (task, handle) = self.new_task(future, keep, Location::caller());
+        if spawner.shared.inject(task).is_err() {
+            // The executor is gone, and the task was never queued.
+            spawner.shared.metrics.dequeued();
+            panic!("{}", SpawnError::Shutdown);
+        }
+        handle
+    }
+
+    // Like `spawn`, with `guard` dropped right after the future.
+    #[track_caller]
+    pub(super) fn spawn_with_guard<F, K>(self, future: F, guard: K) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static + Send,
+        F::Output: Send + 'static,
+        K: Send + 'static,
+    {
+        // Safety: nothing borrowed.
+        unsafe { self.spawn_unchecked(future, guard) }
+    }
+
+    // See `Spawner::try_spawn`.
+    #[track_caller]
+    pub fn try_spawn<F>(self, future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+    where
+        F: Future + 'static + Send,
+        F::Output: Send + 'static,
+    {
+        if self.spawner.is_closed() {
+            return Err(SpawnError::Shutdown);
+        }
+        let spawner = self.spawner;
+        // Safety: nothing borrowed.
+        let (task, handle) = unsafe { self.new_task(future, (), Location::caller()) };
+        match spawner.shared.try_inject(task) {
+            Ok(()) => Ok(handle),
+            Err(task) => {
+                task.discard();
+                Err(SpawnError::Full)
+            }
+        }
+    }
+
+    // Safety: see `spawn_unchecked`.
+    unsafe fn new_task<'a, F, K>(
+        self,
+        future: F,
+        keep: K,
+        location: &'static Location<'static>,
+    ) -> (TaskRef, JoinHandle<F::Output>)
+    where
+        F: Future + Send + 'a,
+        F::Output: Send + 'a,
+        K: Send + 'a,
+    {
+        let spawner = self.spawner;
+        let shared = &spawner.shared;
+        let (join_sender, new_handle) = join_handle::join_pair();
+        let panic_hook = shared.panic_hook.clone();
+        let future = join_sender.run(future, move |payload| {
+            if let Some(hook) = panic_hook {
+                hook(payload);
+            }
+        });
+        let task = Task {
+            id: shared.next_task_id.fetch_add(1, Ordering::Relaxed),
+            name: self.name,
+            location,
+            priority: self.priority,
+            state: state::State::new(),
+            spawner: spawner.clone(),
+            aborted: AtomicBool::new(false),
+            polls: AtomicUsize::new(0),
+            last_poll: AtomicU64::new(u64::MAX),
+        };
+        // Safety: up to the caller.
+        let task = unsafe { TaskRef::new(task, scope::Guarded::new(future, keep)) };
+        shared.metrics.spawned();
+        let handle = new_handle(join_handle::AbortTarget::Task(task.downgrade()));
+        shared.tasks.insert(task.id, task.downgrade());
+        if let Some(hooks) = &shared.hooks {
+            hooks.on_spawn(&task.info());
+        }
+        (task, handle)
+    }
+}
+
+impl Shared {
+    fn live_tasks(&self) -> usize {
+        self.tasks.len()
+    }
+
+    // Push onto the injector of the task's class, and wake a worker for it.
+    // Hands the task back if the injector is full.
+    fn try_inject(&self, task: TaskRef) -> Result<(), TaskRef> {
+        self.injectors[task.priority.index()].push(task)?;
+        self.injected();
+        Ok(())
+    }
+
+    // Push a woken task, past the capacity if need be: it was let in when
+    // it was spawned, and is queued at most once. So a wake never waits.
+    // Hands the task back if the executor is gone.
+    fn reinject(&self, task: TaskRef) -> Result<(), TaskRef> {
+        if self.dropped.load(Ordering::Acquire) {
+            return Err(task);
+        }
+        self.injectors[task.priority.index()].push_unbounded(task);
+        self.injected();
+        Ok(())
+    }
+
+    fn injected(&self) {
+        // The executor may have been dropped while we pushed, after it
+        // emptied the injectors.
+        if self.dropped.load(Ordering::SeqCst) {
+            self.drain();
+        } else {
+            self.parker.notify_one();
+        }
+    }
+
+    // Like `try_inject`, but if the injector is full, spill over into the
+    // local queue on one of our own workers: only they drain the injector,
+    // so they must not wait for room in it. Other threads do wait.
+    // Hands the task back if the executor is gone.
+    fn inject(&self, mut task: TaskRef) -> Result<(), TaskRef> {
+        let mut backoff = Backoff::default();
+        loop {
+            if self.dropped.load(Ordering::Acquire) {
+                return Err(task);
+            }
+            task = match self.try_inject(task).map_err(worker::push_local) {
+                Ok(()) | Err(Ok(())) => return Ok(()),
+                Err(Err(task)) => task,
+            };
+            if backoff.is_completed() {
+                thread::sleep(Duration::from_micros(100));
+            } else {
+                backoff.snooze();
+            }
+        }
+    }
+
+    // Drop every queued task. Queued tasks hold spawners, which hold the
+    // injectors, so this breaks the cycle once the executor is gone.
+    fn drain(&self) {
+        for injector in &self.injectors {
+            while let Some(task) = injector.pop() {
+                self.metrics.dequeued();
+                drop(task);
+            }
+        }
+    }
+}
+
+impl TaskRef {
+    // Poll a task popped from a ready queue.
+    fn run(self) {
+        let shared = &self.spawner.shared;
+        shared.metrics.dequeued();
+        if !self.state.start_running() {
+            // Cancelled while it was queued.
+            return;
+        }
+        // Safety, for all the future accessors below: we moved the task into
+        // `RUNNING`.
+        if self.aborted.load(Ordering::Acquire) {
+            self.cancel();
+            return;
+        }
+        if !unsafe { self.has_future() } {
+            self.state.complete();
+            return;
+        }
+        let waker = self.waker_ref();
+        let context = &mut Context::from_waker(&waker);
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        let since_start = shared.started.elapsed().as_nanos() as u64;
+        self.last_poll.store(since_start, Ordering::Relaxed);
+        let hooks = shared.hooks.as_deref();
+        if let Some(hooks) = hooks {
+            hooks.before_poll(&self.info());
+        }
+        // The task's own panics are caught inside its future. This only
+        // catches one from dropping it, which must not take the worker down
+        // either.
+        let poll = shared.metrics.poll(&self, || {
+            catch_unwind(AssertUnwindSafe(|| {
+                coop::budget(|| unsafe { self.poll_future(context) })
+            }))
         });
-        self.task_sender.send(task).expect("send task wrong");
+        if let Some(hooks) = hooks {
+            let poll = match poll {
+                Ok(Poll::Pending) => Poll::Pending,
+                _ => Poll::Ready(()),
+            };
+            hooks.after_poll(&self.info(), poll);
+        }
+        match poll {
+            Ok(Poll::Pending) => {
+                if self.state.finish_poll() {
+                    self.schedule(true);
+                }
+            }
+            Ok(Poll::Ready(Ok(()))) => {
+                self.state.complete();
+                self.finish(TaskOutcome::Completed);
+            }
+            Ok(Poll::Ready(Err(Panicked))) | Err(_) => {
+                // Leak what is left of a future that panicked while dropping.
+                unsafe { self.leak_future() };
+                self.state.complete();
+                self.finish(TaskOutcome::Panicked);
+            }
+        }
+    }
+
+    fn wake(self) {
+        self.spawner.shared.metrics.woken(&self);
+        if self.state.wake() {
+            self.schedule(false);
+        }
+    }
+
+    fn wake_by_ref(&self) {
+        self.spawner.shared.metrics.woken(self);
+        if self.state.wake() {
+            self.clone().schedule(false);
+        }
+    }
+
+    pub(super) fn abort(&self) {
+        self.aborted.store(true, Ordering::Release);
+        self.wake_by_ref();
+    }
+
+    // Forget a task the ready queue refused, without counting it anywhere.
+    fn discard(self) {
+        let shared = &self.spawner.shared;
+        shared.tasks.remove(self.id);
+        shared.metrics.discarded();
+        // Every `on_spawn` gets its `on_complete`.
+        if let Some(hooks) = &shared.hooks {
+            hooks.on_complete(&self.info(), TaskOutcome::Cancelled);
+        }
+    }
+
+    // Put a task whose state just became `SCHEDULED` into a ready queue.
+    // A task that was woken while it ran goes to the back of the injector,
+    // so one that keeps waking itself cannot hog its worker.
+    fn schedule(self, yielded: bool) {
+        let shared = self.spawner.shared.clone();
+        shared.metrics.queued();
+        // Woken from one of our own workers: keep it on that worker's local
+        // queue instead of going through the injector.
+        let task = match yielded {
+            true => self,
+            false => match worker::push_local_if_busy(self) {
+                Ok(()) => return,
+                Err(task) => task,
+            },
+        };
+        // The executor is gone, and the task with it.
+        if shared.reinject(task).is_err() {
+            shared.metrics.dequeued();
+        }
     }
 }
 
-impl ArcWake for Task {
-    fn wake_by_ref(arc_self: &Arc<Self>) {
-        let cloned = arc_self.clone();
-        arc_self.task_sender.send(cloned).expect("Task send failed");
+impl TaskRef {
+    // Drop the future of a task that is not running, if it is still there.
+    // Returns false if the task is being polled right now.
+    fn try_cancel(&self) -> bool {
+        if self.state.claim() {
+            // `claim` moved the task into `RUNNING`.
+            self.cancel();
+        }
+        !self.state.is_running()
+    }
+
+    // Drop the future if it is still there. This completes the join handle
+    // with `Cancelled`. The task must be in `RUNNING`.
+    fn cancel(&self) {
+        // Safety: see above.
+        let had_future = unsafe { self.has_future() };
+        self.state.complete();
+        if had_future {
+            unsafe { self.drop_future_and_finish() };
+        }
+    }
+
+    // Drop the future if it is still there, and finish the task. A panic
+    // from dropping it must not take the caller down, same as in `run`.
+    // Safety: nobody else touches the future, see `TaskRef::drop_future`.
+    unsafe fn drop_future_and_finish(&self) {
+        match catch_unwind(AssertUnwindSafe(|| unsafe { self.drop_future() })) {
+            Ok(true) => self.finish(TaskOutcome::Cancelled),
+            Ok(false) => {}
+            Err(_) => {
+                unsafe { self.leak_future() };
+                self.finish(TaskOutcome::Panicked);
+            }
+        }
+    }
+}
+
+impl Task {
+    fn finish(&self, outcome: TaskOutcome) {
+        let shared = &self.spawner.shared;
+        if !shared.tasks.remove(self.id) {
+            return;
+        }
+        let counter = match outcome {
+            TaskOutcome::Completed => &shared.completed,
+            TaskOutcome::Cancelled => &shared.cancelled,
+            TaskOutcome::Panicked => &shared.panicked,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        shared.metrics.finished(self.polls.load(Ordering::Relaxed));
+        if let Some(hooks) = &shared.hooks {
+            hooks.on_complete(&self.info(), outcome);
+        }
+    }
+
+    fn info(&self) -> TaskInfo<'_> {
+        TaskInfo {
+            id: self.id,
+            name: self.name.as_deref(),
+        }
     }
 }
 
 impl Executor {
+    // Run on the calling thread only.
     pub fn run(&self) {
-        while let Ok(task) = self.ready_queue.recv() {
-            let mut future_slot = task.future.lock().unwrap();
-            if let Some(mut future) = future_slot.take() {
-                let waker = futures::task::waker_ref(&task);
-                let context = &mut Context::from_waker(&*waker);
-                if let Poll::Pending = future.as_mut().poll(context) {
-                    *future_slot = Some(future);
-                }
+        self.run_with_workers(1);
+    }
+
+    // Run `num_workers` workers, the calling thread being one of them.
+    // Every worker has its own local run queues and steals from the others
+    // when both its queues and the shared ready queues are empty.
+    // Returns once every `Spawner` and every task is gone, or once `shutdown`
+    // has been called and there is no ready task left.
+    pub fn run_with_workers(&self, num_workers: usize) {
+        assert!(num_workers > 0, "need at least one worker");
+        let workers = worker::Workers::new(self.shared.clone(), num_workers, None);
+        thread::scope(|s| {
+            for index in 1..num_workers {
+                let workers = &workers;
+                thread::Builder::new()
+                    .name(format!("excutor-worker-{index}"))
+                    .spawn_scoped(s, move || workers.run(index))
+                    .expect("failed to spawn worker thread");
             }
+            workers.run(0);
+        });
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.dropped.store(true, Ordering::SeqCst);
+        self.shared.drain();
+    }
+}
+
+#[test]
+fn test_join_handle() {
+    use crate::simple_future::TimerFuture;
+    use std::time::Duration;
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let answer = spawner.spawn(async {
+        TimerFuture::new(Duration::from_millis(10)).await;
+        42
+    });
+    let doubled = spawner.spawn(async move { answer.await.unwrap() * 2 });
+    let text = spawner.spawn(async { String::from("hello") });
+    drop(spawner);
+
+    std::thread::scope(|s| {
+        s.spawn(|| executor.run());
+        // Wait from a plain thread while the executor runs on another one.
+        assert_eq!(doubled.join().unwrap(), 84);
+        assert_eq!(text.join().unwrap(), "hello");
+    });
+}
+
+#[test]
+fn test_multi_threaded_workers() {
+    use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let threads = Arc::new(Mutex::new(HashSet::new()));
+    let handles: Vec<_> = (0..16)
+        .map(|i| {
+            let threads = threads.clone();
+            spawner.spawn(async move {
+                // Keep the worker busy so the others have to pick up the rest.
+                thread::sleep(Duration::from_millis(20));
+                threads.lock().unwrap().insert(thread::current().id());
+                i
+            })
+        })
+        .collect();
+    drop(spawner);
+
+    executor.run_with_workers(4);
+    let sum: i32 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(sum, (0..16).sum());
+    assert!(threads.lock().unwrap().len() > 1);
+}
+
+#[test]
+fn test_try_spawn_errors() {
+    let (executor, spawner) = ExecutorBuilder::new().capacity(1).build();
+    assert!(spawner.try_spawn(async {}).is_ok());
+    assert_eq!(spawner.try_spawn(async {}).err(), Some(SpawnError::Full));
+
+    drop(executor);
+    assert_eq!(
+        spawner.try_spawn(async {}).err(),
+        Some(SpawnError::Shutdown)
+    );
+
+    let (executor, spawner) = ExecutorBuilder::new().unbounded().build();
+    for _ in 0..5000 {
+        spawner.try_spawn(async {}).unwrap();
+    }
+    drop(spawner);
+    executor.run();
+}
+
+#[test]
+fn test_wake_on_full_queue() {
+    use std::{pin::Pin, sync::Mutex, task::Poll};
+
+    // Wakes itself a few times before finishing.
+    struct SelfWake(usize);
+    impl Future for SelfWake {
+        type Output = ();
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 == 0 {
+                return Poll::Ready(());
+            }
+            self.0 -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
         }
     }
+
+    let (executor, spawner) = ExecutorBuilder::new().capacity(1).build();
+    let inner = spawner.clone();
+    let handle = spawner.spawn(async move {
+        // The queue only has room for one, the rest must spill over locally
+        // rather than block this worker.
+        let handles: Vec<_> = (0..10).map(|_| inner.spawn(SelfWake(3))).collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    });
+    drop(spawner);
+    executor.run();
+    assert!(handle.is_finished());
+
+    // A wake from any other thread does not wait for room either.
+    let (executor, spawner) = ExecutorBuilder::new().capacity(1).build();
+    let waker = Arc::new(Mutex::new(None));
+    let stored = waker.clone();
+    let mut first = true;
+    let woken = spawner.spawn(std::future::poll_fn(move |cx| {
+        if !std::mem::take(&mut first) {
+            return Poll::Ready(());
+        }
+        *stored.lock().unwrap() = Some(cx.waker().clone());
+        Poll::Pending
+    }));
+    executor.shared.injectors[Priority::Normal.index()]
+        .pop()
+        .unwrap()
+        .run();
+    spawner.spawn(async {});
+    waker.lock().unwrap().take().unwrap().wake();
+    drop(spawner);
+    executor.run();
+    assert!(woken.is_finished());
+
+    // Waking a task whose executor is gone is a no-op.
+    let (executor, spawner) = new_executor_and_spawner();
+    let waker = Arc::new(Mutex::new(None));
+    let stored = waker.clone();
+    spawner.spawn(std::future::poll_fn(move |cx| {
+        *stored.lock().unwrap() = Some(cx.waker().clone());
+        std::task::Poll::<()>::Pending
+    }));
+    executor.shared.injectors[Priority::Normal.index()]
+        .pop()
+        .unwrap()
+        .run();
+    drop((executor, spawner));
+    waker.lock().unwrap().take().unwrap().wake();
+}
+
+#[test]
+fn test_abort() {
+    use crate::simple_future::TimerFuture;
+    use std::time::{Duration, Instant};
+
+    let (executor, spawner) = new_executor_and_spawner();
+
+    // Aborted while still queued: never polled.
+    let polled = Arc::new(AtomicBool::new(false));
+    let flag = polled.clone();
+    let queued = spawner.spawn(async move { flag.store(true, Ordering::Relaxed) });
+    queued.abort();
+
+    // Aborted while parked on a timer a minute out.
+    let parked = spawner.spawn(TimerFuture::new(Duration::from_secs(60)));
+    let abort_parked = parked.abort_handle();
+
+    // Aborted from another thread while it keeps rescheduling itself.
+    let running = spawner.spawn(std::future::poll_fn(|cx| {
+        cx.waker().wake_by_ref();
+        std::task::Poll::<()>::Pending
+    }));
+    drop(spawner);
+
+    // The join handles below tell when the tasks are done.
+    thread::spawn(move || executor.run_with_workers(2));
+    let start = Instant::now();
+    thread::sleep(Duration::from_millis(20));
+    abort_parked.abort();
+    running.abort();
+    assert!(parked.join().unwrap_err().is_cancelled());
+    assert!(start.elapsed() < Duration::from_secs(60));
+    assert!(!polled.load(Ordering::Relaxed));
+    assert!(queued.join().unwrap_err().is_cancelled());
+    assert!(running.join().unwrap_err().is_cancelled());
+}
+
+#[test]
+fn test_wake_dedup() {
+    use std::{future::poll_fn, sync::Mutex, task::Poll};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let polls = Arc::new(AtomicUsize::new(0));
+    let waker = Arc::new(Mutex::new(None));
+
+    let (count, stored) = (polls.clone(), waker.clone());
+    let woken = spawner.spawn(poll_fn(move |cx| {
+        if count.fetch_add(1, Ordering::Relaxed) > 0 {
+            return Poll::Ready(());
+        }
+        *stored.lock().unwrap() = Some(cx.waker().clone());
+        // Woken while running: exactly one more poll.
+        for _ in 0..3 {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }));
+    spawner.spawn(async move {
+        // Runs while the first task is queued again, so these are no-ops.
+        let waker = waker.lock().unwrap().take().unwrap();
+        for _ in 0..100 {
+            waker.wake_by_ref();
+        }
+    });
+    drop(spawner);
+
+    executor.run();
+    assert!(woken.is_finished());
+    assert_eq!(polls.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_panic_isolation() {
+    let panics = Arc::new(AtomicUsize::new(0));
+    let hook_panics = panics.clone();
+    let (executor, spawner) = ExecutorBuilder::new()
+        .panic_hook(move |payload| {
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+            hook_panics.fetch_add(1, Ordering::Relaxed);
+        })
+        .build();
+
+    let panicking = spawner.spawn(async {
+        if true {
+            panic!("boom");
+        }
+    });
+    let handles: Vec<_> = (0..10).map(|i| spawner.spawn(async move { i })).collect();
+    drop(spawner);
+
+    executor.run_with_workers(2);
+    assert_eq!(panics.load(Ordering::Relaxed), 1);
+    let payload = panicking.join().unwrap_err().into_panic();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    let sum: i32 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(sum, 45);
+    assert_eq!(executor.shared.panicked.load(Ordering::Relaxed), 1);
+
+    // Nor does one from dropping the future of a cancelled task.
+    struct PanicOnDrop;
+    impl Drop for PanicOnDrop {
+        fn drop(&mut self) {
+            panic!("boom");
+        }
+    }
+    let (executor, spawner) = new_executor_and_spawner();
+    let guard = PanicOnDrop;
+    let aborted = spawner.spawn(async move { drop(guard) });
+    aborted.abort();
+    let guard = PanicOnDrop;
+    // Keeps no waker, so it is dropped along with its last reference.
+    let forgotten = spawner.spawn(async move {
+        let _guard = guard;
+        std::future::pending::<()>().await
+    });
+    let after = spawner.spawn(async { 1 });
+    drop(spawner);
+
+    executor.run();
+    assert!(aborted.join().is_err());
+    assert!(forgotten.join().is_err());
+    assert_eq!(after.join().unwrap(), 1);
+    assert_eq!(executor.shared.panicked.load(Ordering::Relaxed), 2);
 }
@@ -0,0 +1,148 @@
+use std::{
+    cell::UnsafeCell,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering::{AcqRel, Acquire, Release},
+    },
+    task::Waker,
+};
+
+// Holds the waker of whoever waits on something another thread completes,
+// e.g. a join handle or a timer. The scheme of `futures`' `AtomicWaker`:
+// `register` and `wake` each take the slot for themselves with a bit in
+// `state`. When they meet, the wake is handed over instead of lost.
+pub(crate) struct AtomicWaker {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// Bits of `state`, none set while nobody is at the slot.
+const REGISTERING: usize = 1;
+const WAKING: usize = 2;
+
+// Safety: the slot is only touched by whoever set one of the bits.
+unsafe impl Send for AtomicWaker {}
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+    pub(crate) const fn new() -> Self {
+        AtomicWaker {
+            state: AtomicUsize::new(0),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    // Wake `waker` on the next `wake`. Only one thread may register at a
+    // time, the one polling the waiting future.
+    pub(crate) fn register(&self, waker: &Waker) {
+        match self
+            .state
+            .compare_exchange(0, REGISTERING, Acquire, Acquire)
+            .unwrap_or_else(|state| state)
+        {
+            // Safety: `REGISTERING` is ours, and keeps `wake` out.
+            0 => unsafe {
+                let slot = &mut *self.waker.get();
+                if !slot.as_ref().is_some_and(|old| old.will_wake(waker)) {
+                    *slot = Some(waker.clone());
+                }
+                // A `wake` came in meanwhile and left the waker to us.
+                if self
+                    .state
+                    .compare_exchange(REGISTERING, 0, AcqRel, Acquire)
+                    .is_err()
+                {
+                    let waker = slot.take();
+                    self.state.swap(0, AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            },
+            // Being woken right now, which may be too early for the new
+            // waker to be seen. Wake it ourselves to be sure.
+            WAKING => {
+                waker.wake_by_ref();
+            }
+            // Registering concurrently, which the caller must not do.
+            _ => {}
+        }
+    }
+
+    pub(crate) fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, AcqRel) {
+            // Safety: `WAKING` is ours, and keeps `register` out.
+            0 => {
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Release);
+                waker
+            }
+            // `register` is in, and wakes the waker once done. Or another
+            // `wake` is, which does.
+            _ => None,
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_atomic_waker() {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering::Relaxed},
+            Arc,
+        },
+        task::Wake,
+        thread,
+    };
+
+    struct Flag(AtomicBool);
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Relaxed);
+        }
+    }
+
+    let atomic_waker = AtomicWaker::new();
+    // Nothing registered, nothing to wake.
+    atomic_waker.wake();
+    let flag = Arc::new(Flag(AtomicBool::new(false)));
+    atomic_waker.register(&Waker::from(flag.clone()));
+    atomic_waker.wake();
+    assert!(flag.0.load(Relaxed));
+    // Taken by the first wake.
+    flag.0.store(false, Relaxed);
+    atomic_waker.wake();
+    assert!(!flag.0.load(Relaxed));
+
+    // Registering and waking at once never loses the wake.
+    for _ in 0..1000 {
+        let atomic_waker = AtomicWaker::new();
+        let done = AtomicBool::new(false);
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let mut waiting = false;
+        thread::scope(|s| {
+            s.spawn(|| {
+                done.store(true, Release);
+                atomic_waker.wake();
+            });
+            atomic_waker.register(&Waker::from(flag.clone()));
+            // Not done yet when registered, so the wake must reach us.
+            if !done.load(Acquire) {
+                waiting = true;
+            }
+        });
+        assert!(!waiting || flag.0.load(Relaxed));
+    }
+}
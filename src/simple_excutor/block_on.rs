@@ -0,0 +1,49 @@
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+// Run a future to completion on the calling thread, parking the thread
+// while the future is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        // A wake that lands between `poll` and `park` leaves the unpark token
+        // set, so `park` returns straight away instead of missing it.
+        thread::park();
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[test]
+fn test_block_on() {
+    use crate::simple_future::TimerFuture;
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let output = block_on(async {
+        TimerFuture::new(Duration::from_millis(50)).await;
+        "done"
+    });
+    assert_eq!(output, "done");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
@@ -0,0 +1,192 @@
+use super::{
+    join_handle::{self, AbortTarget},
+    JoinHandle, Spawner,
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// Threads for `Spawner::spawn_blocking`. Started on demand up to a cap, and
+// gone again after sitting idle for a while.
+pub(super) struct BlockingPool {
+    config: Config,
+    state: Mutex<PoolState>,
+    condvar: Condvar,
+}
+
+// Set through `ExecutorBuilder`.
+pub(super) struct Config {
+    pub(super) max_threads: usize,
+    pub(super) keep_alive: Duration,
+    pub(super) thread_name: String,
+}
+
+#[derive(Default)]
+struct PoolState {
+    // Jobs no thread has picked up yet.
+    queue: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    // Idle threads that have been told to pick up a job but have not yet.
+    notified: usize,
+    next_thread_id: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_threads: 512,
+            keep_alive: Duration::from_secs(10),
+            thread_name: "excutor-blocking".into(),
+        }
+    }
+}
+
+impl BlockingPool {
+    pub(super) fn new(config: Config) -> Self {
+        BlockingPool {
+            config,
+            state: Mutex::default(),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn spawn(self: &Arc<Self>, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.push_back(job);
+        if state.idle > state.notified {
+            state.notified += 1;
+            self.condvar.notify_one();
+        } else if state.threads < self.config.max_threads {
+            let id = state.next_thread_id;
+            state.next_thread_id += 1;
+            let pool = self.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("{}-{id}", self.config.thread_name))
+                .spawn(move || pool.run());
+            // Failing that, the job waits for a thread that is already there.
+            if spawned.is_ok() {
+                state.threads += 1;
+            } else if state.threads == 0 {
+                // There is none. Dropping the job completes its handle with
+                // `Cancelled`, outside the lock.
+                let job = state.queue.pop_back();
+                drop(state);
+                drop(job);
+            }
+        }
+        // At the cap the job waits for the next thread to finish its own.
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            state.idle += 1;
+            loop {
+                let (guard, timeout) = self
+                    .condvar
+                    .wait_timeout(state, self.config.keep_alive)
+                    .unwrap();
+                state = guard;
+                if state.notified > 0 {
+                    state.notified -= 1;
+                    break;
+                }
+                if timeout.timed_out() {
+                    state.idle -= 1;
+                    state.threads -= 1;
+                    return;
+                }
+            }
+            state.idle -= 1;
+        }
+    }
+}
+
+impl Spawner {
+    // Run blocking code, such as file IO or a lock, on a thread of the
+    // executor's blocking pool instead of on a worker. The handle resolves
+    // once `f` returns. Aborting it only helps before `f` started.
+    pub fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (join_sender, new_handle) = join_handle::join_pair();
+        let aborted = Arc::new(AtomicBool::new(false));
+        let handle = new_handle(AbortTarget::Blocking(Arc::downgrade(&aborted)));
+        let panic_hook = self.shared.panic_hook.clone();
+        self.shared.blocking.spawn(Box::new(move || {
+            // Dropping the sender completes the handle with `Cancelled`.
+            if !aborted.load(Ordering::Acquire) {
+                join_sender.run_blocking(f, move |payload| {
+                    if let Some(hook) = panic_hook {
+                        hook(payload);
+                    }
+                });
+            }
+        }));
+        handle
+    }
+}
+
+#[test]
+fn test_spawn_blocking() {
+    use super::ExecutorBuilder;
+    use std::sync::atomic::AtomicUsize;
+
+    let (executor, spawner) = ExecutorBuilder::new()
+        .max_blocking_threads(2)
+        .blocking_keep_alive(Duration::from_millis(50))
+        .blocking_thread_name("test-blocking")
+        .build();
+    let running = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let inner = spawner.clone();
+    let counters = (running.clone(), most.clone());
+    let names = spawner.spawn(async move {
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let (running, most) = counters.clone();
+                inner.spawn_blocking(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    thread::current().name().unwrap().to_owned()
+                })
+            })
+            .collect();
+        let mut names = Vec::new();
+        for handle in handles {
+            names.push(handle.await.unwrap());
+        }
+        names
+    });
+    let panicked = spawner.spawn_blocking(|| panic!("boom"));
+    drop(spawner);
+
+    executor.run();
+    let names = names.join().unwrap();
+    assert!(names.iter().all(|name| name.starts_with("test-blocking-")));
+    assert!(most.load(Ordering::SeqCst) <= 2);
+    assert!(panicked.join().unwrap_err().is_panic());
+
+    // The idle threads go away after their keep-alive.
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(executor.shared.blocking.state.lock().unwrap().threads, 0);
+}
@@ -0,0 +1,135 @@
+use std::{
+    cell::Cell,
+    future::poll_fn,
+    task::{Context, Poll},
+};
+
+// How many times a task may get past `poll_proceed` in one poll.
+const BUDGET: u32 = 128;
+
+thread_local! {
+    // What is left of the budget of the task being polled on this thread.
+    // `None` outside of a task, where nothing is limited.
+    static REMAINING: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+// Sets `REMAINING` back to what it was once dropped, so also when a panic
+// unwinds out of the poll.
+struct Restore(Option<u32>);
+
+// Run one poll of a task with a fresh budget.
+pub(super) fn budget<R>(poll: impl FnOnce() -> R) -> R {
+    let _restore = Restore(REMAINING.with(|r| r.replace(Some(BUDGET))));
+    poll()
+}
+
+// Charge one unit of the current task's budget. Leaf futures call this
+// before doing any work, and return `Pending` if it does: the task is then
+// woken right away, but goes to the back of the queue so the others get
+// their turn.
+pub fn poll_proceed(cx: &mut Context<'_>) -> Poll<()> {
+    REMAINING.with(|remaining| match remaining.get() {
+        Some(0) => {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+        Some(n) => {
+            remaining.set(Some(n - 1));
+            Poll::Ready(())
+        }
+        None => Poll::Ready(()),
+    })
+}
+
+// Give the other tasks a turn before continuing.
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        REMAINING.with(|r| r.set(self.0));
+    }
+}
+
+#[test]
+fn test_budget() {
+    use super::new_executor_and_spawner;
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let stop = Arc::new(AtomicBool::new(false));
+    let spins = Arc::new(AtomicUsize::new(0));
+
+    // Only ever sees ready leaf futures, would hog the worker forever
+    // without a budget.
+    let (flag, count) = (stop.clone(), spins.clone());
+    spawner.spawn(async move {
+        while !flag.load(Ordering::Relaxed) {
+            poll_fn(poll_proceed).await;
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    spawner.spawn(async move { stop.store(true, Ordering::Relaxed) });
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for name in ["a", "b"] {
+        let order = order.clone();
+        spawner.spawn(async move {
+            for _ in 0..2 {
+                order.lock().unwrap().push(name);
+                yield_now().await;
+            }
+        });
+    }
+    drop(spawner);
+
+    executor.run();
+    // The call that ran out of budget gets through on the next poll.
+    assert_eq!(spins.load(Ordering::Relaxed), BUDGET as usize + 1);
+    assert_eq!(*order.lock().unwrap(), ["a", "b", "a", "b"]);
+}
+
+#[test]
+fn test_budget_after_panic() {
+    use super::{block_on, new_executor_and_spawner};
+    use std::{future::Future, pin::Pin};
+
+    // Ready right away, then panics while it is dropped.
+    struct PanicOnDrop;
+    impl Future for PanicOnDrop {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+            Poll::Ready(())
+        }
+    }
+    impl Drop for PanicOnDrop {
+        fn drop(&mut self) {
+            panic!("boom");
+        }
+    }
+
+    let (executor, spawner) = new_executor_and_spawner();
+    spawner.spawn(PanicOnDrop);
+    drop(spawner);
+    executor.run();
+    // The task's budget does not outlive it on the thread that ran it.
+    assert_eq!(REMAINING.with(Cell::get), None);
+    block_on(async {
+        for _ in 0..2 * BUDGET {
+            poll_fn(poll_proceed).await;
+        }
+    });
+}
@@ -0,0 +1,189 @@
+use super::{Executor, Shared, Spawner};
+use std::{
+    fmt,
+    panic::Location,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, Once, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+// One line of a task dump.
+#[derive(Debug, Clone)]
+pub struct TaskDump {
+    pub id: usize,
+    pub name: Option<String>,
+    pub location: &'static Location<'static>,
+    pub state: TaskState,
+    pub polls: usize,
+    // `None` if the task has not been polled yet.
+    pub since_last_poll: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    // In a ready queue, waiting for a worker.
+    Queued,
+    // Waiting for a wake. A task stuck here has missed its wakeup.
+    Idle,
+    Running,
+    // Done, about to leave the dump.
+    Finished,
+}
+
+// Bumped by the SIGUSR1 handler, waited on by the dump thread.
+static DUMP_REQUESTS: AtomicU32 = AtomicU32::new(0);
+// Executors to dump on SIGUSR1.
+static WATCHED: Mutex<Vec<Weak<Shared>>> = Mutex::new(Vec::new());
+
+impl Shared {
+    fn dump_tasks(&self) -> Vec<TaskDump> {
+        let tasks = self.tasks.upgrade_all();
+        let since_start = self.started.elapsed();
+        let mut dump: Vec<_> = tasks
+            .iter()
+            .map(|task| {
+                let last_poll = task.last_poll.load(Ordering::Relaxed);
+                TaskDump {
+                    id: task.id,
+                    name: task.name.clone(),
+                    location: task.location,
+                    state: task.state.get(),
+                    polls: task.polls.load(Ordering::Relaxed),
+                    since_last_poll: (last_poll != u64::MAX)
+                        .then(|| since_start.saturating_sub(Duration::from_nanos(last_poll))),
+                }
+            })
+            .collect();
+        dump.sort_by_key(|task| task.id);
+        dump
+    }
+}
+
+impl Executor {
+    // Every live task, oldest first. Can be called from any thread.
+    pub fn dump_tasks(&self) -> Vec<TaskDump> {
+        self.shared.dump_tasks()
+    }
+
+    // Print the task dump to stderr whenever the process gets SIGUSR1, as
+    // long as the executor is alive. Replaces any other SIGUSR1 handler.
+    pub fn dump_on_sigusr1(&self) {
+        static INSTALL: Once = Once::new();
+        let shared = Arc::downgrade(&self.shared);
+        let mut watched = WATCHED.lock().unwrap();
+        // Printed once however often this is called.
+        if !watched.iter().any(|other| other.ptr_eq(&shared)) {
+            watched.push(shared);
+        }
+        drop(watched);
+        INSTALL.call_once(|| {
+            let handler = on_sigusr1 as extern "C" fn(libc::c_int);
+            unsafe { libc::signal(libc::SIGUSR1, handler as libc::sighandler_t) };
+            thread::Builder::new()
+                .name("excutor-task-dump".into())
+                .spawn(dump_thread)
+                .expect("failed to spawn task dump thread");
+        });
+    }
+}
+
+impl Spawner {
+    pub fn dump_tasks(&self) -> Vec<TaskDump> {
+        self.shared.dump_tasks()
+    }
+}
+
+// Only async-signal-safe things in here: an atomic add and a futex wake.
+extern "C" fn on_sigusr1(_: libc::c_int) {
+    DUMP_REQUESTS.fetch_add(1, Ordering::Release);
+    atomic_wait::wake_one(&DUMP_REQUESTS);
+}
+
+fn dump_thread() {
+    let mut seen = 0;
+    loop {
+        atomic_wait::wait(&DUMP_REQUESTS, seen);
+        let requests = DUMP_REQUESTS.load(Ordering::Acquire);
+        if requests == seen {
+            continue;
+        }
+        seen = requests;
+        let executors: Vec<_> = {
+            let mut watched = WATCHED.lock().unwrap();
+            watched.retain(|shared| shared.strong_count() > 0);
+            watched.iter().filter_map(Weak::upgrade).collect()
+        };
+        for (i, shared) in executors.iter().enumerate() {
+            let tasks = shared.dump_tasks();
+            eprintln!("executor {i}: {} live tasks", tasks.len());
+            for task in tasks {
+                eprintln!("  {task}");
+            }
+        }
+    }
+}
+
+impl fmt::Display for TaskDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name:?}")?;
+        }
+        write!(
+            f,
+            " spawned at {}, {:?}, {} polls",
+            self.location, self.state, self.polls
+        )?;
+        match self.since_last_poll {
+            Some(since) => write!(f, ", last polled {since:?} ago"),
+            None => f.write_str(", never polled"),
+        }
+    }
+}
+
+#[test]
+fn test_task_dump() {
+    use super::new_executor_and_spawner;
+    use std::{future::poll_fn, task::Poll};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    // Keeps its waker, but nobody ever uses it.
+    let waker = Arc::new(Mutex::new(None));
+    let stored = waker.clone();
+    let line = line!() + 1;
+    spawner.builder().name("stuck").spawn(poll_fn(move |cx| {
+        *stored.lock().unwrap() = Some(cx.waker().clone());
+        Poll::<()>::Pending
+    }));
+
+    let dump = spawner.dump_tasks();
+    assert_eq!(dump.len(), 1);
+    assert_eq!(dump[0].name.as_deref(), Some("stuck"));
+    assert_eq!(dump[0].location.file(), file!());
+    assert_eq!(dump[0].location.line(), line);
+    assert_eq!(dump[0].state, TaskState::Queued);
+    assert_eq!((dump[0].polls, dump[0].since_last_poll), (0, None));
+    assert!(dump[0]
+        .to_string()
+        .ends_with("Queued, 0 polls, never polled"));
+
+    // Runs forever, as the stuck task never finishes.
+    thread::spawn(move || executor.run());
+    while waker.lock().unwrap().is_none() {
+        thread::yield_now();
+    }
+    thread::sleep(Duration::from_millis(10));
+    let dump = spawner.dump_tasks();
+    assert_eq!((dump[0].state, dump[0].polls), (TaskState::Idle, 1));
+    assert!(dump[0].since_last_poll.unwrap() >= Duration::from_millis(10));
+
+    let (executor, _spawner) = new_executor_and_spawner();
+    executor.dump_on_sigusr1();
+    executor.dump_on_sigusr1();
+    let shared = Arc::downgrade(&executor.shared);
+    let watched = WATCHED.lock().unwrap();
+    assert_eq!(watched.iter().filter(|w| w.ptr_eq(&shared)).count(), 1);
+}
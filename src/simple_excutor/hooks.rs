@@ -0,0 +1,100 @@
+use std::task::Poll;
+
+// Callbacks into the executor's task lifecycle, e.g. for tracing or
+// logging. Registered with `ExecutorBuilder::hooks`.
+// They run inline on the executor's threads, so keep them short, and they
+// must not panic.
+pub trait TaskHooks: Send + Sync + 'static {
+    // Right after the task was created, on the spawning thread.
+    fn on_spawn(&self, _task: &TaskInfo<'_>) {}
+
+    fn before_poll(&self, _task: &TaskInfo<'_>) {}
+
+    // `Ready` also when the task panicked, `on_complete` tells them apart.
+    fn after_poll(&self, _task: &TaskInfo<'_>, _poll: Poll<()>) {}
+
+    // Once per task, when it finishes or its future is dropped. A task
+    // dropped because nothing could wake it any more ends on whatever thread
+    // let go of its last waker.
+    fn on_complete(&self, _task: &TaskInfo<'_>, _outcome: TaskOutcome) {}
+}
+
+// What the hooks get to know about a task.
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo<'a> {
+    pub id: usize,
+    // Set with `TaskBuilder::name`.
+    pub name: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskOutcome {
+    Completed,
+    // Aborted, or dropped before it finished.
+    Cancelled,
+    Panicked,
+}
+
+#[test]
+fn test_hooks() {
+    use super::ExecutorBuilder;
+    use std::{
+        future::poll_fn,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+    impl TaskHooks for Arc<Recorder> {
+        fn on_spawn(&self, task: &TaskInfo<'_>) {
+            self.record(task, "spawn");
+        }
+        fn before_poll(&self, task: &TaskInfo<'_>) {
+            self.record(task, "before");
+        }
+        fn after_poll(&self, task: &TaskInfo<'_>, poll: Poll<()>) {
+            self.record(task, &format!("after {poll:?}"));
+        }
+        fn on_complete(&self, task: &TaskInfo<'_>, outcome: TaskOutcome) {
+            self.record(task, &format!("complete {outcome:?}"));
+        }
+    }
+    impl Recorder {
+        fn record(&self, task: &TaskInfo<'_>, event: &str) {
+            let name = task.name.unwrap_or("?");
+            let line = format!("{}#{} {event}", name, task.id);
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    let recorder = Arc::new(Recorder::default());
+    let (executor, spawner) = ExecutorBuilder::new().hooks(recorder.clone()).build();
+    let mut yielded = false;
+    spawner.builder().name("yield").spawn(poll_fn(move |cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }));
+    let aborted = spawner.spawn(async {});
+    aborted.abort();
+    drop(spawner);
+
+    executor.run();
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        [
+            "yield#0 spawn",
+            "?#1 spawn",
+            "yield#0 before",
+            "yield#0 after Pending",
+            // Woken while it ran, so it goes to the back of the queue.
+            "?#1 complete Cancelled",
+            "yield#0 before",
+            "yield#0 after Ready(())",
+            "yield#0 complete Completed",
+        ]
+    );
+}
@@ -0,0 +1,431 @@
+use std::{
+    cell::UnsafeCell,
+    hint,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{
+        fence, AtomicPtr, AtomicUsize,
+        Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst},
+    },
+    thread,
+};
+
+// A lock-free MPMC queue, optionally bounded. Values go into blocks of
+// `BLOCK_CAP` slots linked into a list, the same scheme as crossbeam's
+// `SegQueue`. `head` and `tail` count positions, of which every block has
+// `LAP`: one per slot, plus one marking the move on to the next block.
+pub(super) struct Injector<T> {
+    head: Position<T>,
+    tail: Tail<T>,
+    // Checked against the distance from `head` to `tail`, so pushes racing
+    // each other may go over it by one each.
+    capacity: Option<usize>,
+}
+
+// The tail, and what a push needs to check the capacity without touching
+// the consumers' cache line.
+struct Tail<T> {
+    position: Position<T>,
+    // An older `head.index`, only brought up to date once the queue looks
+    // full from it. The length it gives is never too short.
+    head_seen: AtomicUsize,
+}
+
+const BLOCK_CAP: usize = 31;
+const LAP: usize = BLOCK_CAP + 1;
+
+// Bits of `Slot::state`.
+const WRITE: usize = 1;
+const READ: usize = 2;
+// The block is being freed, and it is up to this slot's reader to go on.
+const DESTROY: usize = 4;
+
+// On its own cache line, so pushes and pops don't slow each other down.
+#[repr(align(128))]
+struct Position<T> {
+    index: AtomicUsize,
+    block: AtomicPtr<Block<T>>,
+}
+
+struct Block<T> {
+    next: AtomicPtr<Block<T>>,
+    slots: [Slot<T>; BLOCK_CAP],
+}
+
+struct Slot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicUsize,
+}
+
+// Safety: a value is only ever touched by its pusher, then by its popper.
+unsafe impl<T: Send> Send for Injector<T> {}
+unsafe impl<T: Send> Sync for Injector<T> {}
+
+// Spin a little, then yield to whoever we are waiting for.
+#[derive(Default)]
+pub(super) struct Backoff {
+    step: u32,
+}
+
+const SPIN_LIMIT: u32 = 6;
+const YIELD_LIMIT: u32 = 10;
+
+impl<T> Injector<T> {
+    // `None` for no limit on the number of values.
+    pub(super) fn new(capacity: Option<usize>) -> Self {
+        Injector {
+            head: Position::default(),
+            tail: Tail {
+                position: Position::default(),
+                head_seen: AtomicUsize::new(0),
+            },
+            capacity,
+        }
+    }
+
+    // Hands the value back if the queue is full.
+    pub(super) fn push(&self, value: T) -> Result<(), T> {
+        if self.capacity.is_some_and(|capacity| self.is_full(capacity)) {
+            return Err(value);
+        }
+        self.push_unbounded(value);
+        Ok(())
+    }
+
+    // Push even past the capacity.
+    pub(super) fn push_unbounded(&self, value: T) {
+        let mut backoff = Backoff::default();
+        let mut tail = self.tail.position.index.load(Acquire);
+        let mut block = self.tail.position.block.load(Acquire);
+        let mut next_block = None;
+        loop {
+            let offset = tail % LAP;
+            // Another push is moving `tail` on to the next block.
+            if offset == BLOCK_CAP {
+                backoff.snooze();
+                tail = self.tail.position.index.load(Acquire);
+                block = self.tail.position.block.load(Acquire);
+                continue;
+            }
+            // About to take the last slot, so get the next block ready now
+            // to keep the move short.
+            if offset + 1 == BLOCK_CAP && next_block.is_none() {
+                next_block = Some(Block::new());
+            }
+            // The very first push puts in the first block.
+            if block.is_null() {
+                let new = Box::into_raw(Block::new());
+                if self
+                    .tail
+                    .position
+                    .block
+                    .compare_exchange(block, new, Release, Relaxed)
+                    .is_ok()
+                {
+                    self.head.block.store(new, Release);
+                    block = new;
+                } else {
+                    next_block = Some(unsafe { Box::from_raw(new) });
+                    tail = self.tail.position.index.load(Acquire);
+                    block = self.tail.position.block.load(Acquire);
+                    continue;
+                }
+            }
+            match self
+                .tail
+                .position
+                .index
+                .compare_exchange_weak(tail, tail + 1, SeqCst, Acquire)
+            {
+                // Safety: the slot at `offset` is ours, and nobody frees the
+                // block before it has been read.
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next_block = Box::into_raw(next_block.expect("allocated above"));
+                        self.tail.position.block.store(next_block, Release);
+                        // Step over the marker position.
+                        self.tail.position.index.fetch_add(1, Release);
+                        (*block).next.store(next_block, Release);
+                    }
+                    let slot = (*block).slots.get_unchecked(offset);
+                    slot.value.get().write(MaybeUninit::new(value));
+                    slot.state.fetch_or(WRITE, Release);
+                    return;
+                },
+                Err(actual) => {
+                    tail = actual;
+                    block = self.tail.position.block.load(Acquire);
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    pub(super) fn pop(&self) -> Option<T> {
+        let mut backoff = Backoff::default();
+        let mut head = self.head.index.load(Acquire);
+        let mut block = self.head.block.load(Acquire);
+        loop {
+            let offset = head % LAP;
+            // Another pop is moving `head` on to the next block.
+            if offset == BLOCK_CAP {
+                backoff.snooze();
+                head = self.head.index.load(Acquire);
+                block = self.head.block.load(Acquire);
+                continue;
+            }
+            // Pairs with the `SeqCst` CAS in `push`: a value pushed before we
+            // looked is seen.
+            fence(SeqCst);
+            if head == self.tail.position.index.load(Relaxed) {
+                return None;
+            }
+            // The first push has not put in its block yet.
+            if block.is_null() {
+                backoff.snooze();
+                head = self.head.index.load(Acquire);
+                block = self.head.block.load(Acquire);
+                continue;
+            }
+            match self
+                .head
+                .index
+                .compare_exchange_weak(head, head + 1, SeqCst, Acquire)
+            {
+                // Safety: the slot at `offset` is ours to read, and the block
+                // stays around until we say so.
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next = (*block).wait_next();
+                        self.head.block.store(next, Release);
+                        self.head.index.store(head + 2, Release);
+                    }
+                    let slot = (*block).slots.get_unchecked(offset);
+                    slot.wait_write();
+                    let value = slot.value.get().read().assume_init();
+                    // The last slot's reader frees the block, unless some
+                    // reader of an earlier slot is not done yet. Then the
+                    // last of those does.
+                    if offset + 1 == BLOCK_CAP {
+                        Block::destroy(block, 0);
+                    } else if slot.state.fetch_or(READ, AcqRel) & DESTROY != 0 {
+                        Block::destroy(block, offset + 1);
+                    }
+                    return Some(value);
+                },
+                Err(actual) => {
+                    head = actual;
+                    block = self.head.block.load(Acquire);
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    // Whether there are `capacity` values pushed and not popped yet, give
+    // or take the ones in flight.
+    fn is_full(&self, capacity: usize) -> bool {
+        // Positions past the start of the block's lap, less its marker.
+        let count = |index: usize| index / LAP * BLOCK_CAP + (index % LAP).min(BLOCK_CAP);
+        let tail = count(self.tail.position.index.load(Acquire));
+        let head_seen = self.tail.head_seen.load(Relaxed);
+        if tail.saturating_sub(count(head_seen)) < capacity {
+            return false;
+        }
+        let head = self.head.index.load(Acquire);
+        self.tail.head_seen.fetch_max(head, Relaxed);
+        tail.saturating_sub(count(head)) >= capacity
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        let head = self.head.index.load(SeqCst);
+        let tail = self.tail.position.index.load(SeqCst);
+        head == tail
+    }
+}
+
+impl<T> Drop for Injector<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.index.get_mut();
+        let tail = *self.tail.position.index.get_mut();
+        let mut block = *self.head.block.get_mut();
+        // Safety: nobody else is left, so drop what is still queued and free
+        // the blocks it is in.
+        unsafe {
+            while head != tail {
+                let offset = head % LAP;
+                if offset < BLOCK_CAP {
+                    let slot = (*block).slots.get_unchecked(offset);
+                    (*slot.value.get()).assume_init_drop();
+                } else {
+                    let next = *(*block).next.get_mut();
+                    drop(Box::from_raw(block));
+                    block = next;
+                }
+                head += 1;
+            }
+            if !block.is_null() {
+                drop(Box::from_raw(block));
+            }
+        }
+    }
+}
+
+impl<T> Default for Position<T> {
+    fn default() -> Self {
+        Position {
+            index: AtomicUsize::new(0),
+            block: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<T> Block<T> {
+    fn new() -> Box<Self> {
+        Box::new(Block {
+            next: AtomicPtr::new(ptr::null_mut()),
+            slots: std::array::from_fn(|_| Slot {
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+                state: AtomicUsize::new(0),
+            }),
+        })
+    }
+
+    // The push that took the last slot sets `next` right after.
+    fn wait_next(&self) -> *mut Self {
+        let mut backoff = Backoff::default();
+        loop {
+            let next = self.next.load(Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            backoff.snooze();
+        }
+    }
+
+    // Free the block once the slots from `start` on have been read. Hands
+    // that over to the reader of the first one that has not been.
+    // Safety: every slot before `start` has been read.
+    unsafe fn destroy(this: *mut Self, start: usize) {
+        // The last slot's reader always gets here, no need to check it.
+        for i in start..BLOCK_CAP - 1 {
+            let slot = (*this).slots.get_unchecked(i);
+            if slot.state.load(Acquire) & READ == 0
+                && slot.state.fetch_or(DESTROY, AcqRel) & READ == 0
+            {
+                return;
+            }
+        }
+        drop(Box::from_raw(this));
+    }
+}
+
+impl<T> Slot<T> {
+    // The push that took the slot may not have written to it yet.
+    fn wait_write(&self) {
+        let mut backoff = Backoff::default();
+        while self.state.load(Acquire) & WRITE == 0 {
+            backoff.snooze();
+        }
+    }
+}
+
+impl Backoff {
+    // After a lost race: the winner is already done.
+    fn spin(&mut self) {
+        for _ in 0..1 << self.step.min(SPIN_LIMIT) {
+            hint::spin_loop();
+        }
+        if self.step <= SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    // While some other thread finishes what it started.
+    pub(super) fn snooze(&mut self) {
+        if self.step <= SPIN_LIMIT {
+            for _ in 0..1 << self.step {
+                hint::spin_loop();
+            }
+        } else {
+            thread::yield_now();
+        }
+        if self.step <= YIELD_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    // Waited long enough that blocking some other way would be better.
+    pub(super) fn is_completed(&self) -> bool {
+        self.step > YIELD_LIMIT
+    }
+}
+
+#[test]
+fn test_injector() {
+    use std::sync::Arc;
+
+    // Bounded, across a block boundary.
+    let queue = Injector::new(Some(40));
+    for i in 0..40 {
+        queue.push(i).unwrap();
+    }
+    assert_eq!(queue.push(40), Err(40));
+    assert!((0..40).all(|i| queue.pop() == Some(i)));
+    assert!(queue.is_empty());
+    assert_eq!(queue.pop(), None);
+
+    // Four producers and four consumers at once.
+    let queue = Injector::new(None);
+    let total = AtomicUsize::new(0);
+    let popped: Vec<Vec<usize>> = thread::scope(|s| {
+        for producer in 0..4 {
+            let queue = &queue;
+            s.spawn(move || {
+                for i in 0..10_000 {
+                    queue.push(producer * 10_000 + i).unwrap();
+                }
+            });
+        }
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut popped = Vec::new();
+                    while total.load(Relaxed) < 40_000 {
+                        match queue.pop() {
+                            Some(value) => {
+                                popped.push(value);
+                                total.fetch_add(1, Relaxed);
+                            }
+                            None => thread::yield_now(),
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+        consumers.into_iter().map(|c| c.join().unwrap()).collect()
+    });
+    // Each producer's values come out in the order they went in.
+    for values in &popped {
+        for producer in 0..4 {
+            let own = values.iter().filter(|&&v| v / 10_000 == producer);
+            assert!(own.clone().zip(own.skip(1)).all(|(a, b)| a < b));
+        }
+    }
+    let mut all: Vec<_> = popped.concat();
+    all.sort_unstable();
+    assert_eq!(all, (0..40_000).collect::<Vec<_>>());
+
+    // Whatever is left is dropped along with the queue.
+    let value = Arc::new(());
+    let queue = Injector::new(None);
+    for _ in 0..100 {
+        queue.push(value.clone()).unwrap();
+    }
+    for _ in 0..50 {
+        queue.pop();
+    }
+    drop(queue);
+    assert_eq!(Arc::strong_count(&value), 1);
+}
@@ -0,0 +1,238 @@
+use super::{block_on, coop, local::LocalTask, AtomicWaker, WeakTaskRef};
+use std::{
+    any::Any,
+    fmt,
+    future::{poll_fn, Future},
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{ready, Context, Poll},
+};
+
+#[derive(Debug)]
+pub enum JoinError {
+    // The task was aborted, or dropped by the executor before it finished.
+    Cancelled,
+    // The task panicked, carries the panic payload.
+    Panicked(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panicked(_))
+    }
+
+    // The panic payload, e.g. to pass on to `std::panic::resume_unwind`.
+    // Panics if the task did not panic.
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        match self {
+            JoinError::Panicked(payload) => payload,
+            JoinError::Cancelled => panic!("task was cancelled, not panicked"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => f.write_str("task was cancelled"),
+            JoinError::Panicked(_) => f.write_str("task panicked"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+// The receiving half of a spawned task, resolves to the task's output.
+// Dropping it detaches the task, it keeps running.
+pub struct JoinHandle<T> {
+    state: Arc<JoinState<T>>,
+    abort: AbortHandle,
+}
+
+// Cancels a task without waiting for it.
+// Only holds a weak reference, so it never keeps the task alive.
+#[derive(Clone)]
+pub struct AbortHandle {
+    task: AbortTarget,
+}
+
+// Every kind of task an `AbortHandle` can point at. Aborting marks the task
+// and schedules it, so its executor drops the future the next time it picks
+// the task up, wherever the task currently is.
+#[derive(Clone)]
+pub(super) enum AbortTarget {
+    Task(WeakTaskRef),
+    Local(Weak<LocalTask>),
+    // Flag checked by a `spawn_blocking` job before it starts.
+    Blocking(Weak<AtomicBool>),
+}
+
+struct JoinState<T> {
+    completed: AtomicBool,
+    output: Mutex<Option<Result<T, JoinError>>>,
+    waker: AtomicWaker,
+}
+
+// The task's half. Completes the handle with `Cancelled` if the task's
+// future is dropped before it could send its output.
+pub(super) struct JoinSender<T> {
+    state: Arc<JoinState<T>>,
+}
+
+pub(super) fn join_pair<T>() -> (JoinSender<T>, impl FnOnce(AbortTarget) -> JoinHandle<T>) {
+    let state = Arc::new(JoinState {
+        completed: AtomicBool::new(false),
+        output: Mutex::new(None),
+        waker: AtomicWaker::new(),
+    });
+    let sender = JoinSender {
+        state: state.clone(),
+    };
+    (sender, |task| JoinHandle {
+        state,
+        abort: AbortHandle { task },
+    })
+}
+
+impl<T> JoinState<T> {
+    fn complete(&self, output: Result<T, JoinError>) {
+        *self.output.lock().unwrap() = Some(output);
+        // Release pairs with the Acquire in `poll`, so the output is visible
+        // to whoever observes `completed == true`.
+        self.completed.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+// Returned by `JoinSender::run` when the task's future panicked.
+pub(super) struct Panicked;
+
+impl<T> JoinSender<T> {
+    // Drive the task's future, catching a panic instead of letting it unwind
+    // into the executor. The output, or the panic payload, goes to the join
+    // handle. `on_panic` gets to look at the payload first.
+    pub(super) async fn run<F>(
+        self,
+        future: F,
+        on_panic: impl FnOnce(&(dyn Any + Send)),
+    ) -> Result<(), Panicked>
+    where
+        F: Future<Output = T>,
+    {
+        let mut future = pin!(future);
+        let output =
+            poll_fn(
+                |cx| match catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+                    Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+                    Ok(Poll::Pending) => Poll::Pending,
+                    Err(payload) => Poll::Ready(Err(payload)),
+                },
+            )
+            .await;
+        match output {
+            Ok(output) => {
+                self.state.complete(Ok(output));
+                Ok(())
+            }
+            Err(payload) => {
+                on_panic(&*payload);
+                self.state.complete(Err(JoinError::Panicked(payload)));
+                Err(Panicked)
+            }
+        }
+    }
+}
+
+impl<T> JoinSender<T> {
+    // Like `run`, for a blocking closure.
+    pub(super) fn run_blocking(
+        self,
+        f: impl FnOnce() -> T,
+        on_panic: impl FnOnce(&(dyn Any + Send)),
+    ) {
+        match catch_unwind(AssertUnwindSafe(f)) {
+            Ok(output) => self.state.complete(Ok(output)),
+            Err(payload) => {
+                on_panic(&*payload);
+                self.state.complete(Err(JoinError::Panicked(payload)));
+            }
+        }
+    }
+}
+
+impl<T> Drop for JoinSender<T> {
+    fn drop(&mut self) {
+        if !self.state.completed.load(Ordering::Acquire) {
+            self.state.complete(Err(JoinError::Cancelled));
+        }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.state.completed.load(Ordering::Acquire)
+    }
+
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.abort.clone()
+    }
+
+    // Block the current thread until the task completes.
+    // Must not be called from a task running on the same single-threaded
+    // executor, because that task would never get the chance to finish.
+    pub fn join(self) -> Result<T, JoinError> {
+        block_on(self)
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        ready!(coop::poll_proceed(cx));
+        if !self.state.completed.load(Ordering::Acquire) {
+            self.state.waker.register(cx.waker());
+
+            // Check again after `register`, same as `TimerFuture`.
+            if !self.state.completed.load(Ordering::Acquire) {
+                return Poll::Pending;
+            }
+        }
+        let output = self.state.output.lock().unwrap().take();
+        Poll::Ready(output.expect("JoinHandle polled after completion"))
+    }
+}
+
+impl AbortHandle {
+    // Does nothing if the task already finished.
+    pub fn abort(&self) {
+        match &self.task {
+            AbortTarget::Task(task) => {
+                if let Some(task) = task.upgrade() {
+                    task.abort();
+                }
+            }
+            AbortTarget::Local(task) => {
+                if let Some(task) = task.upgrade() {
+                    task.abort();
+                }
+            }
+            AbortTarget::Blocking(aborted) => {
+                if let Some(aborted) = aborted.upgrade() {
+                    aborted.store(true, Ordering::Release);
+                }
+            }
+        }
+    }
+}
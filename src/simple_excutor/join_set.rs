@@ -0,0 +1,236 @@
+use super::{AbortHandle, JoinError, JoinHandle, Spawner};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::{
+    collections::HashMap,
+    future::{poll_fn, Future},
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// A set of tasks whose results come out in the order they finish.
+// Dropping the set aborts the tasks that are still in it.
+pub struct JoinSet<T> {
+    spawner: Spawner,
+    handles: FuturesUnordered<JoinHandle<T>>,
+    fail_fast: Option<FailFast<T>>,
+}
+
+struct FailFast<T> {
+    is_failure: fn(&T) -> bool,
+    siblings: Arc<Siblings>,
+}
+
+// The tasks of a fail-fast set that have not finished yet.
+#[derive(Default)]
+struct Siblings {
+    // Set by the first task to fail. Its siblings are aborted once its
+    // output is out, see `Member`.
+    failed: AtomicBool,
+    tasks: Mutex<HashMap<usize, AbortHandle>>,
+    next_id: AtomicUsize,
+}
+
+impl<T: Send + 'static> JoinSet<T> {
+    pub fn new(spawner: &Spawner) -> Self {
+        JoinSet {
+            spawner: spawner.clone(),
+            handles: FuturesUnordered::new(),
+            fail_fast: None,
+        }
+    }
+
+    #[track_caller]
+    pub fn spawn<F>(&mut self, future: F) -> AbortHandle
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let handle = match &self.fail_fast {
+            None => self.spawner.spawn(future),
+            Some(fail_fast) => {
+                let siblings = fail_fast.siblings.clone();
+                let id = siblings.next_id();
+                let task = watch(future, fail_fast.is_failure, siblings.clone());
+                let member = Member {
+                    siblings: siblings.clone(),
+                    id,
+                };
+                let handle = self.spawner.builder().spawn_with_guard(task, member);
+                siblings.add(id, handle.abort_handle());
+                handle
+            }
+        };
+        let abort = handle.abort_handle();
+        self.handles.push(handle);
+        abort
+    }
+
+    // The output of the next task to finish, `None` once the set is empty.
+    // Aborted tasks come out as `JoinError::Cancelled`.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        self.handles.next().await
+    }
+
+    pub fn abort_all(&self) {
+        for handle in self.handles.iter() {
+            handle.abort();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+
+impl<V: Send + 'static, E: Send + 'static> JoinSet<Result<V, E>> {
+    // A set that aborts all of its tasks as soon as one of them returns an
+    // `Err` or panics, including the ones spawned after that.
+    pub fn fail_fast(spawner: &Spawner) -> Self {
+        let mut set = JoinSet::new(spawner);
+        set.fail_fast = Some(FailFast {
+            is_failure: Result::is_err,
+            siblings: Arc::default(),
+        });
+        set
+    }
+}
+
+impl<T> Drop for JoinSet<T> {
+    fn drop(&mut self) {
+        for handle in self.handles.iter() {
+            handle.abort();
+        }
+    }
+}
+
+// Dropped right after the future of task `id` of a fail-fast set, so only
+// once its output has reached its join handle.
+struct Member {
+    siblings: Arc<Siblings>,
+    id: usize,
+}
+
+// Run `future` as a task of a fail-fast set.
+async fn watch<F: Future>(
+    future: F,
+    is_failure: fn(&F::Output) -> bool,
+    siblings: Arc<Siblings>,
+) -> F::Output {
+    let mut future = pin!(future);
+    let output = poll_fn(|cx| {
+        match catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            // The panic itself still goes to the task's join handle.
+            Err(payload) => {
+                siblings.failed.store(true, Ordering::Release);
+                resume_unwind(payload)
+            }
+        }
+    })
+    .await;
+    if is_failure(&output) {
+        siblings.failed.store(true, Ordering::Release);
+    }
+    output
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        if self.siblings.failed.load(Ordering::Acquire) {
+            self.siblings.fail();
+        } else {
+            self.siblings.remove(self.id);
+        }
+    }
+}
+
+impl Siblings {
+    fn next_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn add(&self, id: usize, task: AbortHandle) {
+        if self.failed.load(Ordering::Acquire) {
+            task.abort();
+            return;
+        }
+        self.tasks.lock().unwrap().insert(id, task);
+        // Lost the race with a failing sibling.
+        if self.failed.load(Ordering::Acquire) {
+            self.fail();
+        }
+    }
+
+    fn remove(&self, id: usize) {
+        self.tasks.lock().unwrap().remove(&id);
+    }
+
+    fn fail(&self) {
+        self.failed.store(true, Ordering::Release);
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks.into_values() {
+            task.abort();
+        }
+    }
+}
+
+#[test]
+fn test_join_set() {
+    use super::{block_on, new_executor_and_spawner};
+    use crate::simple_future::TimerFuture;
+    use std::{
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    let (executor, spawner) = new_executor_and_spawner();
+    thread::spawn(move || executor.run_with_workers(2));
+
+    block_on(async {
+        let mut set = JoinSet::new(&spawner);
+        for ms in [30, 10, 20] {
+            set.spawn(async move {
+                TimerFuture::new(Duration::from_millis(ms)).await;
+                ms
+            });
+        }
+        let mut order = Vec::new();
+        while let Some(output) = set.join_next().await {
+            order.push(output.unwrap());
+        }
+        assert_eq!(order, [10, 20, 30]);
+
+        let start = Instant::now();
+        let mut set = JoinSet::fail_fast(&spawner);
+        set.spawn(async {
+            TimerFuture::new(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        set.spawn(async {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            Err("failed")
+        });
+        assert_eq!(set.join_next().await.unwrap().unwrap(), Err("failed"));
+        assert!(set.join_next().await.unwrap().unwrap_err().is_cancelled());
+        assert!(set.join_next().await.is_none());
+        assert!(start.elapsed() < Duration::from_secs(60));
+    });
+
+    let (alive, dropped) = mpsc::channel::<()>();
+    let mut set = JoinSet::new(&spawner);
+    set.spawn(async move {
+        let _alive = alive;
+        TimerFuture::new(Duration::from_secs(60)).await;
+    });
+    drop(set);
+    let recv = dropped.recv_timeout(Duration::from_secs(10));
+    assert_eq!(recv, Err(mpsc::RecvTimeoutError::Disconnected));
+}
@@ -0,0 +1,391 @@
+use super::{
+    coop,
+    injector::Injector,
+    join_handle::{self, AbortTarget, JoinHandle},
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::Future,
+    mem::ManuallyDrop,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{fence, AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    thread::{self, Thread},
+};
+
+// A single-threaded executor for futures that are not `Send`.
+// The futures never leave the thread that owns the executor, only their
+// small headers do, so tasks can still be woken from any thread.
+pub struct LocalExecutor {
+    pub(super) inner: Rc<Inner>,
+}
+
+pub(super) struct Inner {
+    pub(super) ready_queue: Arc<ReadyQueue>,
+    // Keyed by task id. A task's future is taken out while it is polled, so
+    // it can spawn more tasks without a `RefCell` borrow conflict.
+    futures: RefCell<HashMap<usize, Slot>>,
+    next_task_id: Cell<usize>,
+}
+
+struct Slot {
+    // Dead once every waker for the task is gone.
+    task: Weak<LocalTask>,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+// Woken tasks, pushed from any thread.
+pub(super) struct ReadyQueue {
+    pub(super) tasks: Injector<Arc<LocalTask>>,
+    // The thread that owns the executor, unparked by every wake.
+    owner: Thread,
+    // Set once the executor is gone. Wakes drop the task after that.
+    closed: AtomicBool,
+}
+
+// What a waker points at. `Send + Sync`, unlike the future it stands for.
+pub(super) struct LocalTask {
+    id: usize,
+    aborted: AtomicBool,
+    // Set while the task sits in the ready queue, so it goes in only once
+    // however often it is woken.
+    queued: AtomicBool,
+    ready_queue: Arc<ReadyQueue>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<Inner>>> = const { RefCell::new(None) };
+}
+
+// Sets `CURRENT` back to what it was once dropped, so also when a panic
+// unwinds out of `LocalExecutor::enter`.
+struct Restore(Option<Rc<Inner>>);
+
+// Spawn a task onto the `LocalExecutor` running on this thread.
+// Panics when called outside of `LocalExecutor::run`.
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let inner = CURRENT.with(|c| c.borrow().clone());
+    let inner = inner.expect("spawn_local called outside of LocalExecutor::run");
+    inner.spawn(future)
+}
+
+impl LocalExecutor {
+    pub fn new() -> Self {
+        LocalExecutor {
+            inner: Rc::new(Inner {
+                ready_queue: Arc::new(ReadyQueue {
+                    tasks: Injector::new(None),
+                    owner: thread::current(),
+                    closed: AtomicBool::new(false),
+                }),
+                futures: RefCell::default(),
+                next_task_id: Cell::new(0),
+            }),
+        }
+    }
+
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        self.inner.spawn(future)
+    }
+
+    // Run until every task has finished or can no longer be woken.
+    pub fn run(&self) {
+        self.enter(|| self.inner.run());
+    }
+
+    // Make `spawn_local` spawn onto this executor while `f` runs.
+    pub(super) fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _restore = Restore(CURRENT.with(|c| c.replace(Some(self.inner.clone()))));
+        f()
+    }
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|c| *c.borrow_mut() = self.0.take());
+    }
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inner {
+    fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (join_sender, new_handle) = join_handle::join_pair();
+        let future = Box::pin(async move {
+            // A panic ends up in the join handle, the other tasks keep going.
+            let _ = join_sender.run(future, |_| {}).await;
+        });
+        let id = self.next_task_id.get();
+        self.next_task_id.set(id + 1);
+        let task = Arc::new(LocalTask {
+            id,
+            aborted: AtomicBool::new(false),
+            queued: AtomicBool::new(false),
+            ready_queue: self.ready_queue.clone(),
+        });
+        let handle = new_handle(AbortTarget::Local(Arc::downgrade(&task)));
+        let slot = Slot {
+            task: Arc::downgrade(&task),
+            future,
+        };
+        self.futures.borrow_mut().insert(id, slot);
+        task.wake();
+        handle
+    }
+
+    fn run(&self) {
+        loop {
+            let Some(task) = self.ready_queue.tasks.pop() else {
+                self.drop_unwakeable();
+                if self.futures.borrow().is_empty() {
+                    return;
+                }
+                // Until some waker fires. A wake since the `pop` above
+                // makes this return right away.
+                thread::park();
+                continue;
+            };
+            self.poll(task);
+        }
+    }
+
+    pub(super) fn poll(&self, task: Arc<LocalTask>) {
+        // Wakes from here on queue the task again. Pairs with the swap in
+        // `LocalTask::wake`: a wake that found it still queued is seen.
+        task.queued.swap(false, Ordering::AcqRel);
+        // Already finished.
+        let Some(mut slot) = self.futures.borrow_mut().remove(&task.id) else {
+            return;
+        };
+        if task.aborted.load(Ordering::Acquire) {
+            // Dropping the future completes its join handle with `Cancelled`.
+            slot.drop_future();
+            return;
+        }
+        let waker = task.waker_ref();
+        let context = &mut Context::from_waker(&waker);
+        // The task's own panics are caught inside its future. This only
+        // catches one from dropping it once it is done.
+        let poll = catch_unwind(AssertUnwindSafe(|| {
+            coop::budget(|| slot.future.as_mut().poll(context))
+        }));
+        match poll {
+            Ok(Poll::Pending) => {
+                self.futures.borrow_mut().insert(task.id, slot);
+            }
+            Ok(Poll::Ready(())) | Err(_) => slot.drop_future(),
+        }
+    }
+
+    // A pending task whose wakers are all gone will never run again.
+    pub(super) fn drop_unwakeable(&self) {
+        let dead: Vec<_> = {
+            let mut futures = self.futures.borrow_mut();
+            let mut ids: Vec<usize> = futures
+                .iter()
+                .filter(|(_, slot)| slot.task.strong_count() == 0)
+                .map(|(id, _)| *id)
+                .collect();
+            // In a stable order, for `Simulation`.
+            ids.sort_unstable();
+            ids.into_iter()
+                .filter_map(|id| futures.remove(&id))
+                .collect()
+        };
+        // Dropped outside the borrow, their destructors may spawn or wake.
+        for slot in dead {
+            slot.drop_future();
+        }
+    }
+}
+
+impl Slot {
+    // A panic from dropping the future must not take the other tasks down,
+    // same as for `Executor`.
+    fn drop_future(self) {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(self.future)));
+    }
+}
+
+impl Drop for Inner {
+    // Queued tasks hold on to the queue. Drop them to break the cycle.
+    fn drop(&mut self) {
+        self.ready_queue.closed.store(true, Ordering::SeqCst);
+        self.ready_queue.drain();
+    }
+}
+
+impl ReadyQueue {
+    fn drain(&self) {
+        while self.tasks.pop().is_some() {}
+    }
+}
+
+impl LocalTask {
+    // Hands our count of the task to the ready queue, no clone needed.
+    fn wake(self: Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.enqueue();
+        }
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // Already queued, spare the clone.
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.clone().enqueue();
+        }
+    }
+
+    fn enqueue(self: Arc<Self>) {
+        let queue = self.ready_queue.clone();
+        // The executor is gone, and the future with it.
+        if queue.closed.load(Ordering::Acquire) {
+            return;
+        }
+        queue.tasks.push_unbounded(self);
+        // Pairs with the store in `Inner::drop`: either it sees our task,
+        // or we see it closed and drop the task ourselves.
+        fence(Ordering::SeqCst);
+        if queue.closed.load(Ordering::Relaxed) {
+            queue.drain();
+        } else {
+            queue.owner.unpark();
+        }
+    }
+
+    // Borrow the task as a `Waker`, without touching the reference count.
+    fn waker_ref(self: &Arc<Self>) -> ManuallyDrop<Waker> {
+        let raw = RawWaker::new(Arc::as_ptr(self).cast(), &VTABLE);
+        ManuallyDrop::new(unsafe { Waker::from_raw(raw) })
+    }
+
+    pub(super) fn abort(self: &Arc<Self>) {
+        self.aborted.store(true, Ordering::Release);
+        self.wake_by_ref();
+    }
+}
+
+// Same scheme as the wakers in `task_ref`: a waker owns one count of the
+// `Arc<LocalTask>` it points at.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(ptr: *const ()) -> RawWaker {
+    Arc::increment_strong_count(ptr.cast::<LocalTask>());
+    RawWaker::new(ptr, &VTABLE)
+}
+
+unsafe fn wake(ptr: *const ()) {
+    Arc::from_raw(ptr.cast::<LocalTask>()).wake();
+}
+
+unsafe fn wake_by_ref(ptr: *const ()) {
+    ManuallyDrop::new(Arc::from_raw(ptr.cast::<LocalTask>())).wake_by_ref();
+}
+
+unsafe fn drop_waker(ptr: *const ()) {
+    drop(Arc::from_raw(ptr.cast::<LocalTask>()));
+}
+
+#[test]
+fn test_local_executor() {
+    use crate::simple_future::TimerFuture;
+    use std::time::Duration;
+
+    let executor = LocalExecutor::new();
+    let counter = Rc::new(RefCell::new(0));
+
+    let shared = counter.clone();
+    let parent = executor.spawn_local(async move {
+        // `Rc` held across an await, woken from the timer thread.
+        TimerFuture::new(Duration::from_millis(10)).await;
+        *shared.borrow_mut() += 1;
+        let inner = shared.clone();
+        let child = spawn_local(async move {
+            *inner.borrow_mut() += 1;
+            Rc::new("child")
+        });
+        child.await.unwrap()
+    });
+
+    let endless = executor.spawn_local(TimerFuture::new(Duration::from_secs(60)));
+    let abort = endless.abort_handle();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        abort.abort();
+    });
+
+    executor.run();
+    assert_eq!(*counter.borrow(), 2);
+    assert_eq!(*parent.join().unwrap(), "child");
+    assert!(endless.join().unwrap_err().is_cancelled());
+}
+
+#[test]
+fn test_local_drop_panic() {
+    use super::yield_now;
+    use std::future::pending;
+
+    // Ready right away, then panics while it is dropped.
+    struct PanicOnDrop;
+    impl Future for PanicOnDrop {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+            Poll::Ready(())
+        }
+    }
+    impl Drop for PanicOnDrop {
+        fn drop(&mut self) {
+            panic!("boom");
+        }
+    }
+
+    let executor = LocalExecutor::new();
+    // Panics while dropped once aborted.
+    let guard = PanicOnDrop;
+    let aborted = executor.spawn_local(async move {
+        let _guard = guard;
+        pending::<()>().await
+    });
+    aborted.abort_handle().abort();
+    // Panics while dropped once nothing can wake it any more.
+    let guard = PanicOnDrop;
+    executor.spawn_local(async move {
+        let _guard = guard;
+        pending::<()>().await
+    });
+    let finished = executor.spawn_local(PanicOnDrop);
+    let survivor = executor.spawn_local(async {
+        yield_now().await;
+        1
+    });
+
+    executor.run();
+    assert!(aborted.join().unwrap_err().is_cancelled());
+    assert!(finished.join().is_ok());
+    assert_eq!(survivor.join().unwrap(), 1);
+    // No longer spawning onto the executor that has stopped.
+    let spawned = catch_unwind(|| spawn_local(async {}));
+    assert!(spawned.is_err());
+}
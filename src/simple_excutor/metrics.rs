@@ -0,0 +1,240 @@
+use super::{Executor, Shared, Spawner, Task};
+use std::{
+    cell::Cell,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+    time::Instant,
+};
+
+// Counters bumped by the executor as it goes. All of them are relaxed
+// atomics, so keeping them up to date costs next to nothing.
+#[derive(Default)]
+pub(super) struct Metrics {
+    spawned: AtomicUsize,
+    polls: AtomicUsize,
+    // Tasks sitting in a ready queue, local or global.
+    queued: AtomicUsize,
+    wakeups: AtomicUsize,
+    self_wakes: AtomicUsize,
+    polls_per_task: AtomicHistogram,
+    poll_durations: AtomicHistogram,
+}
+
+// A point-in-time copy of an executor's metrics.
+// The fields are read one by one while the executor keeps running, so they
+// are not guaranteed to add up exactly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub spawned: usize,
+    // Finished tasks, by how they finished.
+    pub completed: usize,
+    pub cancelled: usize,
+    pub panicked: usize,
+    // Spawned and not finished yet.
+    pub alive: usize,
+    pub polls: usize,
+    // How many times each finished task was polled.
+    pub polls_per_task: Histogram,
+    // Tasks waiting in the ready queues.
+    pub queue_depth: usize,
+    // Every wake of a task, including ones that had no effect because the
+    // task was queued already.
+    pub wakeups: usize,
+    // Wakes of a task from inside its own `poll`.
+    pub self_wakes: usize,
+    // Time spent in each `poll`, in microseconds.
+    pub poll_durations: Histogram,
+    // Syscalls made to wake up a sleeping worker.
+    pub worker_unparks: usize,
+}
+
+// Counts of values in power-of-two buckets: bucket 0 holds zero, bucket `i`
+// holds `2^(i-1)..2^i`, and the last one everything above.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    pub buckets: [usize; BUCKETS],
+}
+
+const BUCKETS: usize = 16;
+
+#[derive(Default)]
+struct AtomicHistogram {
+    buckets: [AtomicUsize; BUCKETS],
+}
+
+thread_local! {
+    // The task being polled on this thread, to tell self-wakes apart.
+    static POLLING: Cell<*const Task> = const { Cell::new(ptr::null()) };
+}
+
+impl Histogram {
+    // Smallest value that goes into bucket `index`.
+    pub fn lower_bound(index: usize) -> u64 {
+        match index {
+            0 => 0,
+            _ => 1 << (index - 1),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.buckets.iter().sum()
+    }
+}
+
+impl AtomicHistogram {
+    fn record(&self, value: u64) {
+        let index = (u64::BITS - value.leading_zeros()) as usize;
+        self.buckets[index.min(BUCKETS - 1)].fetch_add(1, Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        Histogram {
+            buckets: self.buckets.each_ref().map(|bucket| bucket.load(Relaxed)),
+        }
+    }
+}
+
+impl Metrics {
+    // A task was created, it starts out on its way into a ready queue.
+    pub(super) fn spawned(&self) {
+        self.spawned.fetch_add(1, Relaxed);
+        self.queued.fetch_add(1, Relaxed);
+    }
+
+    // A task that was never queued after all.
+    pub(super) fn discarded(&self) {
+        self.spawned.fetch_sub(1, Relaxed);
+        self.queued.fetch_sub(1, Relaxed);
+    }
+
+    pub(super) fn queued(&self) {
+        self.queued.fetch_add(1, Relaxed);
+    }
+
+    pub(super) fn dequeued(&self) {
+        self.queued.fetch_sub(1, Relaxed);
+    }
+
+    pub(super) fn woken(&self, task: &Task) {
+        self.wakeups.fetch_add(1, Relaxed);
+        if ptr::eq(POLLING.with(Cell::get), task) {
+            self.self_wakes.fetch_add(1, Relaxed);
+        }
+    }
+
+    pub(super) fn finished(&self, polls: usize) {
+        self.polls_per_task.record(polls as u64);
+    }
+
+    // Poll `task` through `poll`, timing it.
+    pub(super) fn poll<R>(&self, task: &Task, poll: impl FnOnce() -> R) -> R {
+        let previous = POLLING.with(|p| p.replace(task));
+        let start = Instant::now();
+        let result = poll();
+        let elapsed = start.elapsed();
+        POLLING.with(|p| p.set(previous));
+        self.polls.fetch_add(1, Relaxed);
+        self.poll_durations.record(elapsed.as_micros() as u64);
+        result
+    }
+}
+
+impl Shared {
+    fn metrics(&self) -> MetricsSnapshot {
+        let metrics = &self.metrics;
+        MetricsSnapshot {
+            spawned: metrics.spawned.load(Relaxed),
+            completed: self.completed.load(Relaxed),
+            cancelled: self.cancelled.load(Relaxed),
+            panicked: self.panicked.load(Relaxed),
+            alive: self.live_tasks(),
+            polls: metrics.polls.load(Relaxed),
+            polls_per_task: metrics.polls_per_task.snapshot(),
+            queue_depth: metrics.queued.load(Relaxed),
+            wakeups: metrics.wakeups.load(Relaxed),
+            self_wakes: metrics.self_wakes.load(Relaxed),
+            poll_durations: metrics.poll_durations.snapshot(),
+            worker_unparks: self.parker.unparks(),
+        }
+    }
+}
+
+impl Executor {
+    // Can be called from any thread, also while `run` is going on.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.shared.metrics()
+    }
+}
+
+impl Spawner {
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.shared.metrics()
+    }
+}
+
+#[test]
+fn test_metrics() {
+    use super::new_executor_and_spawner;
+    use std::{future::poll_fn, task::Poll, thread, time::Duration};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let mut wakes = 3;
+    spawner.spawn(poll_fn(move |cx| {
+        if wakes == 0 {
+            return Poll::Ready(());
+        }
+        wakes -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }));
+    spawner.spawn(async { thread::sleep(Duration::from_millis(2)) });
+    let before = spawner.metrics();
+    assert_eq!(
+        (before.spawned, before.alive, before.queue_depth),
+        (2, 2, 2)
+    );
+    drop(spawner);
+
+    executor.run();
+    let after = executor.metrics();
+    assert_eq!((after.completed, after.alive, after.queue_depth), (2, 0, 0));
+    assert_eq!(after.polls, 5);
+    assert_eq!((after.wakeups, after.self_wakes), (3, 3));
+    // Polled 4 times and once.
+    assert_eq!(after.polls_per_task.buckets[3], 1);
+    assert_eq!(after.polls_per_task.buckets[1], 1);
+    assert_eq!(after.poll_durations.count(), 5);
+    // The sleeping poll took at least 2ms.
+    assert_eq!(Histogram::lower_bound(11), 1024);
+    assert_eq!(after.poll_durations.buckets[11..].iter().sum::<usize>(), 1);
+}
+
+#[test]
+fn test_queue_depth_after_shutdown() {
+    use super::new_executor_and_spawner;
+    use std::{
+        future::poll_fn,
+        sync::{Arc, Mutex},
+        task::{Poll, Waker},
+        thread,
+        time::Duration,
+    };
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let waker = Arc::new(Mutex::new(None::<Waker>));
+    let registered = waker.clone();
+    spawner.spawn(poll_fn(move |cx| {
+        *registered.lock().unwrap() = Some(cx.waker().clone());
+        Poll::<()>::Pending
+    }));
+    // Wakes the task above into the worker's `next` slot, then keeps the
+    // worker busy until the deadline has passed.
+    spawner.spawn(async move {
+        waker.lock().unwrap().take().unwrap().wake();
+        thread::sleep(Duration::from_millis(20));
+    });
+
+    let report = executor.shutdown(Duration::from_millis(10));
+    assert_eq!((report.completed, report.cancelled), (1, 1));
+    assert_eq!(spawner.metrics().queue_depth, 0);
+}
@@ -0,0 +1,159 @@
+use std::{
+    sync::atomic::{fence, AtomicU32, AtomicUsize, Ordering::SeqCst},
+    time::Instant,
+};
+
+// Where idle workers sleep, on a futex.
+// A worker with nothing to do is first searching: it looks through the
+// queues once more, stealing included. Only then does it go to sleep.
+// Waking a worker only takes a syscall if one is asleep and nobody is
+// searching, as a searching worker will find the new task by itself.
+#[derive(Default)]
+pub(super) struct Parker {
+    // Bumped by every wake. Sleepers wait for it to move.
+    epoch: AtomicU32,
+    searching: AtomicUsize,
+    // Includes workers about to sleep, see `prepare_sleep`.
+    sleeping: AtomicUsize,
+    // Futex wakes made, for the metrics.
+    unparks: AtomicUsize,
+}
+
+impl Parker {
+    // Call after pushing a task onto a shared queue.
+    pub(super) fn notify_one(&self) {
+        // Pairs with the one in `prepare_sleep`: either we see the worker
+        // sleeping, or it sees our task when it looks again.
+        fence(SeqCst);
+        if self.searching.load(SeqCst) == 0 && self.sleeping.load(SeqCst) > 0 {
+            self.epoch.fetch_add(1, SeqCst);
+            self.unparks.fetch_add(1, SeqCst);
+            atomic_wait::wake_one(&self.epoch);
+        }
+    }
+
+    // Wake every sleeper, to have them look at the executor's state.
+    pub(super) fn notify_all(&self) {
+        fence(SeqCst);
+        if self.sleeping.load(SeqCst) > 0 {
+            self.epoch.fetch_add(1, SeqCst);
+            self.unparks.fetch_add(1, SeqCst);
+            atomic_wait::wake_all(&self.epoch);
+        }
+    }
+
+    // Workers that are searching or asleep. A busy worker leaves woken tasks
+    // to them.
+    pub(super) fn idle(&self) -> usize {
+        self.searching.load(SeqCst) + self.sleeping.load(SeqCst)
+    }
+
+    pub(super) fn start_searching(&self) {
+        self.searching.fetch_add(1, SeqCst);
+    }
+
+    // A searching worker found a task. If it was the last one searching,
+    // any other task pushed meanwhile went without a wake, so pass one on.
+    pub(super) fn found_task(&self) {
+        if self.searching.fetch_sub(1, SeqCst) == 1 {
+            self.notify_one();
+        }
+    }
+
+    pub(super) fn stop_searching(&self) {
+        self.searching.fetch_sub(1, SeqCst);
+    }
+
+    // Turn from searching to sleeping. The caller must look through the
+    // queues once more before `sleep`, tasks pushed from here on wake it.
+    pub(super) fn prepare_sleep(&self) -> u32 {
+        self.sleeping.fetch_add(1, SeqCst);
+        self.searching.fetch_sub(1, SeqCst);
+        let epoch = self.epoch.load(SeqCst);
+        fence(SeqCst);
+        epoch
+    }
+
+    // Back to searching without sleeping.
+    pub(super) fn cancel_sleep(&self) {
+        self.searching.fetch_add(1, SeqCst);
+        self.sleeping.fetch_sub(1, SeqCst);
+    }
+
+    // Sleep until woken, or until `deadline`, then search again. Returns
+    // false if the deadline passed.
+    pub(super) fn sleep(&self, epoch: u32, deadline: Option<Instant>) -> bool {
+        match deadline {
+            None => atomic_wait::wait(&self.epoch, epoch),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    self.sleeping.fetch_sub(1, SeqCst);
+                    return false;
+                }
+                wait_timeout(&self.epoch, epoch, deadline - now);
+            }
+        }
+        self.cancel_sleep();
+        true
+    }
+
+    pub(super) fn unparks(&self) -> usize {
+        self.unparks.load(SeqCst)
+    }
+}
+
+// `atomic_wait::wait` with a timeout, which it does not have.
+#[cfg(target_os = "linux")]
+fn wait_timeout(futex: &AtomicU32, expected: u32, timeout: std::time::Duration) {
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+        tv_nsec: timeout.subsec_nanos().into(),
+    };
+    // Refer to the futex (2) man page for the syscall signature.
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex as *const AtomicU32,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            &timeout as *const libc::timespec,
+        );
+    }
+}
+
+// No timed futex wait here, so nap in short steps instead.
+#[cfg(not(target_os = "linux"))]
+fn wait_timeout(futex: &AtomicU32, expected: u32, timeout: std::time::Duration) {
+    let deadline = Instant::now() + timeout;
+    while futex.load(SeqCst) == expected && Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_parker() {
+    use super::new_executor_and_spawner;
+    use std::{thread, time::Duration};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    // Nobody sleeps yet, so nobody needs a wake.
+    let handles: Vec<_> = (0..100).map(|i| spawner.spawn(async move { i })).collect();
+    let parker = &executor.shared.parker;
+    assert_eq!(parker.unparks(), 0);
+
+    thread::scope(|s| {
+        s.spawn(|| executor.run_with_workers(2));
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), i);
+        }
+        while parker.sleeping.load(SeqCst) < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let before = parker.unparks();
+        assert_eq!(spawner.spawn(async { 7 }).join().unwrap(), 7);
+        assert!(parker.unparks() > before);
+        // Dropping the last spawner wakes the sleepers to let them exit.
+        drop(spawner);
+    });
+}
@@ -0,0 +1,186 @@
+use super::{new_executor_and_spawner, JoinHandle, SpawnError, Spawner};
+use std::{
+    cell::Cell,
+    future::Future,
+    io, mem,
+    sync::mpsc,
+    thread::{self, JoinHandle as ThreadHandle},
+};
+
+// One single-worker executor per CPU, each on a thread pinned to its CPU.
+// Every core only runs its own ready queue, nothing is ever stolen. Tasks
+// move to another core only when spawned there through its `CoreHandle`.
+pub struct ThreadPerCore {
+    cores: Vec<CoreHandle>,
+    threads: Vec<ThreadHandle<()>>,
+}
+
+// Spawns onto one core. Keeps that core's executor running, like a
+// `Spawner`.
+#[derive(Clone)]
+pub struct CoreHandle {
+    cpu: usize,
+    spawner: Spawner,
+}
+
+thread_local! {
+    static CURRENT_CPU: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+impl ThreadPerCore {
+    // A core for every CPU this process may run on.
+    pub fn new() -> io::Result<Self> {
+        Self::on_cpus(allowed_cpus()?)
+    }
+
+    pub fn on_cpus(cpus: impl IntoIterator<Item = usize>) -> io::Result<Self> {
+        let mut runtime = ThreadPerCore {
+            cores: Vec::new(),
+            threads: Vec::new(),
+        };
+        for cpu in cpus {
+            // Out of reach of a `cpu_set_t`, `CPU_SET` would panic on it.
+            if cpu >= libc::CPU_SETSIZE as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("CPU {cpu} is out of range"),
+                ));
+            }
+            let (executor, spawner) = new_executor_and_spawner();
+            let (pinned_sender, pinned) = mpsc::channel();
+            let thread = thread::Builder::new()
+                .name(format!("excutor-core-{cpu}"))
+                .spawn(move || {
+                    let result = pin_to_cpu(cpu);
+                    let ok = result.is_ok();
+                    let _ = pinned_sender.send(result);
+                    if ok {
+                        CURRENT_CPU.with(|c| c.set(Some(cpu)));
+                        executor.run();
+                    }
+                })?;
+            runtime.threads.push(thread);
+            // On error the cores started so far are joined when `runtime`
+            // drops.
+            pinned.recv().expect("core thread exited")?;
+            runtime.cores.push(CoreHandle { cpu, spawner });
+        }
+        Ok(runtime)
+    }
+
+    pub fn cores(&self) -> &[CoreHandle] {
+        &self.cores
+    }
+
+    // The CPU of the core running the calling task, `None` outside of one.
+    pub fn current_cpu() -> Option<usize> {
+        CURRENT_CPU.with(Cell::get)
+    }
+
+    // Wait for every core to run out of tasks, see `Executor::run`. Handles
+    // given out elsewhere keep their core running.
+    pub fn join(mut self) {
+        self.join_cores();
+    }
+
+    fn join_cores(&mut self) {
+        self.cores.clear();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ThreadPerCore {
+    fn drop(&mut self) {
+        self.join_cores();
+    }
+}
+
+impl CoreHandle {
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+
+    // For the other ways to spawn, e.g. `Spawner::builder`.
+    pub fn spawner(&self) -> &Spawner {
+        &self.spawner
+    }
+
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static + Send,
+        F::Output: Send + 'static,
+    {
+        self.spawner.spawn(future)
+    }
+
+    #[track_caller]
+    pub fn try_spawn<F>(&self, future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+    where
+        F: Future + 'static + Send,
+        F::Output: Send + 'static,
+    {
+        self.spawner.try_spawn(future)
+    }
+}
+
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    // Safety: `cpu_set_t` is plain data, all zeroes is an empty set.
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, mem::size_of_val(&set), &mut set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let cpus = (0..libc::CPU_SETSIZE as usize).filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) });
+    Ok(cpus.collect())
+}
+
+// Pin the calling thread.
+fn pin_to_cpu(cpu: usize) -> io::Result<()> {
+    // Safety: as above.
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+    if unsafe { libc::sched_setaffinity(0, mem::size_of_val(&set), &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_thread_per_core() {
+    let runtime = ThreadPerCore::new().unwrap();
+    let cores = runtime.cores();
+    assert!(!cores.is_empty());
+    let last = cores.last().unwrap().clone();
+    let handles: Vec<_> = cores
+        .iter()
+        .map(|core| {
+            let last = last.clone();
+            core.spawn(async move {
+                let here = (ThreadPerCore::current_cpu(), unsafe {
+                    libc::sched_getcpu()
+                });
+                // Hop over to the last core explicitly.
+                let there = last
+                    .spawn(async { ThreadPerCore::current_cpu() })
+                    .await
+                    .unwrap();
+                (here, there)
+            })
+        })
+        .collect();
+    drop(last);
+
+    for (core, handle) in cores.iter().zip(handles) {
+        let ((current, actual), there) = handle.join().unwrap();
+        assert_eq!(current, Some(core.cpu()));
+        assert_eq!(actual as usize, core.cpu());
+        assert_eq!(there, Some(cores.last().unwrap().cpu()));
+    }
+    runtime.join();
+
+    assert!(ThreadPerCore::on_cpus([libc::CPU_SETSIZE as usize - 1]).is_err());
+    let error = ThreadPerCore::on_cpus([0, libc::CPU_SETSIZE as usize]).err();
+    assert_eq!(error.unwrap().kind(), io::ErrorKind::InvalidInput);
+}
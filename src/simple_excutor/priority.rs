@@ -0,0 +1,73 @@
+// Scheduling class of a task. Workers favor higher classes, see `Aging`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub(super) const COUNT: usize = 3;
+
+    // Position of the class's queue, higher classes first.
+    pub(super) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// A class with ready tasks that has been passed over this many times in a
+// row gets the next turn, whatever is waiting above it.
+const AGING_LIMIT: u32 = 8;
+
+// Per-worker bookkeeping for anti-starvation aging.
+#[derive(Default)]
+pub(super) struct Aging {
+    skipped: [u32; Priority::COUNT],
+}
+
+impl Aging {
+    // Choose which class to serve next, given which ones have ready tasks.
+    pub(super) fn pick(&mut self, ready: [bool; Priority::COUNT]) -> Option<usize> {
+        // A lower class ages on every pick above it, so the lowest starved
+        // class has waited the longest.
+        let class = (0..Priority::COUNT)
+            .rev()
+            .find(|&class| ready[class] && self.skipped[class] >= AGING_LIMIT)
+            .or_else(|| ready.iter().position(|&ready| ready))?;
+        self.skipped[class] = 0;
+        for (skipped, ready) in self.skipped.iter_mut().zip(ready).skip(class + 1) {
+            if ready {
+                *skipped += 1;
+            }
+        }
+        Some(class)
+    }
+}
+
+#[test]
+fn test_priority_aging() {
+    use super::new_executor_and_spawner;
+    use std::sync::{Arc, Mutex};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for priority in [Priority::Low, Priority::High] {
+        for _ in 0..20 {
+            let order = order.clone();
+            spawner.spawn_with_priority(priority, async move {
+                order.lock().unwrap().push(priority);
+            });
+        }
+    }
+    drop(spawner);
+
+    executor.run();
+    let order = order.lock().unwrap();
+    assert_eq!(order.len(), 40);
+    // High goes first, but Low gets a turn long before High runs dry.
+    assert!(order[..AGING_LIMIT as usize]
+        .iter()
+        .all(|&p| p == Priority::High));
+    assert_eq!(order[AGING_LIMIT as usize], Priority::Low);
+}
@@ -0,0 +1,102 @@
+use super::{TaskRef, WeakTaskRef};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+const SHARDS: usize = 64;
+
+// Every task that has been spawned and has not finished yet. Only
+// `dump_tasks` and `shutdown` look through it, so it is split into shards
+// by task id, and threads spawning and finishing tasks rarely meet on the
+// same lock. The count is kept apart, to be read without any lock.
+pub(super) struct Registry {
+    shards: [Mutex<HashMap<usize, WeakTaskRef>>; SHARDS],
+    live: AtomicUsize,
+}
+
+impl Registry {
+    pub(super) fn insert(&self, id: usize, task: WeakTaskRef) {
+        self.live.fetch_add(1, Ordering::Relaxed);
+        self.shard(id).lock().unwrap().insert(id, task);
+    }
+
+    // False if the task was not there, so each one is removed only once.
+    pub(super) fn remove(&self, id: usize) -> bool {
+        let removed = self.shard(id).lock().unwrap().remove(&id);
+        if removed.is_some() {
+            self.live.fetch_sub(1, Ordering::Release);
+        }
+        removed.is_some()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.live.load(Ordering::Acquire)
+    }
+
+    // The tasks that are still alive, in no particular order.
+    pub(super) fn upgrade_all(&self) -> Vec<TaskRef> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock().unwrap();
+                shard
+                    .values()
+                    .filter_map(WeakTaskRef::upgrade)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn shard(&self, id: usize) -> &Mutex<HashMap<usize, WeakTaskRef>> {
+        &self.shards[id % SHARDS]
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            shards: std::array::from_fn(|_| Mutex::default()),
+            live: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[test]
+fn test_registry() {
+    use super::new_executor_and_spawner;
+    use std::thread;
+
+    let (executor, spawner) = new_executor_and_spawner();
+    // Spawned from several threads at once, into different shards.
+    let handles: Vec<_> = thread::scope(|s| {
+        let spawners: Vec<_> = (0..4)
+            .map(|_| {
+                let spawner = spawner.clone();
+                s.spawn(move || {
+                    (0..200)
+                        .map(|i| spawner.spawn(async move { i }))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        spawners
+            .into_iter()
+            .flat_map(|s| s.join().unwrap())
+            .collect()
+    });
+    assert_eq!(executor.metrics().alive, 800);
+    let dump = executor.dump_tasks();
+    assert_eq!(dump.len(), 800);
+    assert!(dump.windows(2).all(|w| w[0].id < w[1].id));
+    drop(spawner);
+
+    executor.run_with_workers(2);
+    let sum: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(sum, 4 * (0..200).sum::<usize>());
+    assert_eq!(executor.metrics().alive, 0);
+    assert!(executor.dump_tasks().is_empty());
+}
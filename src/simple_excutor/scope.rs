@@ -0,0 +1,212 @@
+use super::{block_on, JoinError, JoinHandle, Spawner};
+use std::{
+    future::{poll_fn, Future},
+    marker::PhantomData,
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicIsize, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    thread::{self, Thread},
+};
+
+// Spawns tasks that may borrow from outside the scope, see `Spawner::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    spawner: &'scope Spawner,
+    state: Arc<ScopeState>,
+    // Same variance as `std::thread::Scope`.
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+// A `JoinHandle` that cannot outlive its scope.
+pub struct ScopedJoinHandle<'scope, T> {
+    handle: JoinHandle<T>,
+    state: &'scope ScopeState,
+}
+
+struct ScopeState {
+    // Scoped tasks whose futures have not been dropped yet.
+    running: AtomicUsize,
+    // Panics no handle has been awaited for. Dips below zero for a moment
+    // if a handle sees the panic before the task itself is done.
+    unjoined_panics: AtomicIsize,
+    // The thread blocked in `Spawner::scope`.
+    owner: Thread,
+}
+
+// Dropped right after a scoped task's future.
+struct ScopeGuard(Arc<ScopeState>);
+
+// A future that holds on to `keep` until it is dropped itself.
+pub(super) struct Guarded<F, K> {
+    // Declared first, so dropped first.
+    future: F,
+    _keep: K,
+}
+
+impl Spawner {
+    // Like `std::thread::scope`, for tasks: the futures spawned on the scope
+    // may borrow from outside of it, and `scope` only returns once all of
+    // them are gone, be it completed, aborted or panicked, and even if `f`
+    // panics.
+    // This blocks the calling thread instead of being a future, as a scope
+    // future could be forgotten while its tasks still hold their borrows. So
+    // it must not be called from a task on one of this executor's workers.
+    // Panics if a scoped task panicked and its handle was not awaited.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            spawner: self,
+            state: Arc::new(ScopeState {
+                running: AtomicUsize::new(0),
+                unjoined_panics: AtomicIsize::new(0),
+                owner: thread::current(),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        while scope.state.running.load(Ordering::Acquire) != 0 {
+            thread::park();
+        }
+        match result {
+            Err(payload) => resume_unwind(payload),
+            Ok(_) if scope.state.unjoined_panics.load(Ordering::Relaxed) > 0 => {
+                panic!("a scoped task panicked")
+            }
+            Ok(result) => result,
+        }
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    #[track_caller]
+    pub fn spawn<F>(&'scope self, future: F) -> ScopedJoinHandle<'scope, F::Output>
+    where
+        F: Future + Send + 'scope,
+        F::Output: Send + 'scope,
+    {
+        self.state.running.fetch_add(1, Ordering::Relaxed);
+        let guard = ScopeGuard(self.state.clone());
+        let state = self.state.clone();
+        let mut future = Box::pin(future);
+        let future = poll_fn(move |cx| {
+            match catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+                Ok(poll) => poll,
+                // Count it, then let the task's join handle have it.
+                Err(payload) => {
+                    state.unjoined_panics.fetch_add(1, Ordering::Relaxed);
+                    resume_unwind(payload)
+                }
+            }
+        });
+        // Safety: `scope` waits for the guard, which goes right after the
+        // future.
+        let handle = unsafe { self.spawner.builder().spawn_unchecked(future, guard) };
+        ScopedJoinHandle {
+            handle,
+            state: &self.state,
+        }
+    }
+}
+
+impl<T> ScopedJoinHandle<'_, T> {
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    pub fn abort(&self) {
+        self.handle.abort();
+    }
+
+    // Same caveat as `JoinHandle::join`.
+    pub fn join(self) -> Result<T, JoinError> {
+        block_on(self)
+    }
+}
+
+impl<T> Future for ScopedJoinHandle<'_, T> {
+    type Output = Result<T, JoinError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let output = ready!(Pin::new(&mut self.handle).poll(cx));
+        if matches!(&output, Err(error) if error.is_panic()) {
+            self.state.unjoined_panics.fetch_sub(1, Ordering::Relaxed);
+        }
+        Poll::Ready(output)
+    }
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        if self.0.running.fetch_sub(1, Ordering::Release) == 1 {
+            self.0.owner.unpark();
+        }
+    }
+}
+
+impl<F, K> Guarded<F, K> {
+    pub(super) fn new(future: F, keep: K) -> Self {
+        Guarded {
+            future,
+            _keep: keep,
+        }
+    }
+}
+
+impl<F: Future, K> Future for Guarded<F, K> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // Safety: `future` is never moved out of a pinned `Guarded`.
+        unsafe { self.map_unchecked_mut(|guarded| &mut guarded.future) }.poll(cx)
+    }
+}
+
+#[test]
+fn test_scope() {
+    use super::new_executor_and_spawner;
+    use crate::simple_future::TimerFuture;
+    use std::{sync::atomic::AtomicBool, time::Duration};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    thread::scope(|s| {
+        s.spawn(|| executor.run_with_workers(2));
+
+        let mut numbers = vec![1, 2, 3];
+        let total = AtomicUsize::new(0);
+        let doubled = spawner.scope(|scope| {
+            for n in &numbers {
+                let total = &total;
+                scope.spawn(async move {
+                    TimerFuture::new(Duration::from_millis(5)).await;
+                    total.fetch_add(*n, Ordering::Relaxed);
+                });
+            }
+            let doubled = scope.spawn(async { numbers.iter().map(|n| n * 2).collect::<Vec<_>>() });
+            doubled.join().unwrap()
+        });
+        // Every task is done, so the borrows are over.
+        assert_eq!(total.load(Ordering::Relaxed), 6);
+        numbers.push(4);
+        assert_eq!(doubled, [2, 4, 6]);
+
+        // Waits for the other task before passing on the panic.
+        let finished = AtomicBool::new(false);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            spawner.scope(|scope| {
+                scope.spawn(async { panic!("boom") });
+                scope.spawn(async {
+                    TimerFuture::new(Duration::from_millis(20)).await;
+                    finished.store(true, Ordering::Relaxed);
+                });
+            })
+        }));
+        assert!(result.is_err());
+        assert!(finished.load(Ordering::Relaxed));
+        drop(spawner);
+    });
+}
@@ -0,0 +1,98 @@
+use super::{worker::Workers, Executor};
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+// What happened to the tasks that were still alive when shutdown started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    // Ran to completion before the deadline.
+    pub completed: usize,
+    // Aborted, or dropped because they had not finished by the deadline.
+    pub cancelled: usize,
+    // Panicked before the deadline.
+    pub panicked: usize,
+    // Still being polled by another thread at the deadline, so their futures
+    // could not be dropped.
+    pub leaked: usize,
+}
+
+impl Executor {
+    // Stop accepting new tasks, keep running the ones in flight on the
+    // calling thread for up to `timeout`, then drop whatever is left.
+    // Any `run` going on at the same time returns once it runs out of
+    // ready tasks.
+    pub fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        let shared = &self.shared;
+        shared.closed.store(true, Ordering::Release);
+        shared.parker.notify_all();
+        let completed = shared.completed.load(Ordering::Relaxed);
+        let cancelled = shared.cancelled.load(Ordering::Relaxed);
+        let panicked = shared.panicked.load(Ordering::Relaxed);
+
+        let deadline = Instant::now() + timeout;
+        let workers = Workers::new(shared.clone(), 1, Some(deadline));
+        workers.run(0);
+        drop(workers);
+
+        let mut leaked = 0;
+        for task in shared.tasks.upgrade_all() {
+            if !task.try_cancel() {
+                leaked += 1;
+            }
+        }
+
+        ShutdownReport {
+            completed: shared.completed.load(Ordering::Relaxed) - completed,
+            cancelled: shared.cancelled.load(Ordering::Relaxed) - cancelled,
+            panicked: shared.panicked.load(Ordering::Relaxed) - panicked,
+            leaked,
+        }
+    }
+}
+
+#[test]
+fn test_shutdown() {
+    use super::new_executor_and_spawner;
+    use crate::simple_future::TimerFuture;
+    use std::{future::pending, thread};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let quick = spawner.spawn(async {
+        TimerFuture::new(Duration::from_millis(10)).await;
+        1
+    });
+    // Finishes while shutdown is draining.
+    let draining = spawner.spawn(TimerFuture::new(Duration::from_millis(80)));
+    // Won't finish in time.
+    let slow = spawner.spawn(TimerFuture::new(Duration::from_secs(60)));
+    let forever = spawner.spawn(async {
+        TimerFuture::new(Duration::from_millis(10)).await;
+        pending::<()>().await
+    });
+
+    let start = Instant::now();
+    thread::scope(|s| {
+        // `run` would never return on its own here, as `slow` keeps the
+        // executor alive for a minute.
+        s.spawn(|| executor.run());
+        thread::sleep(Duration::from_millis(50));
+        let report = executor.shutdown(Duration::from_millis(100));
+        assert_eq!(
+            report,
+            ShutdownReport {
+                completed: 1,
+                cancelled: 1,
+                panicked: 0,
+                leaked: 0,
+            }
+        );
+    });
+    assert!(start.elapsed() < Duration::from_secs(60));
+    assert_eq!(quick.join().unwrap(), 1);
+    assert!(draining.join().is_ok());
+    assert!(slow.join().unwrap_err().is_cancelled());
+    assert!(forever.join().unwrap_err().is_cancelled());
+    assert!(spawner.try_spawn(async {}).is_err());
+}
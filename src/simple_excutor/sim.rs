@@ -0,0 +1,246 @@
+use super::{JoinHandle, LocalExecutor};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    future::Future,
+    rc::Rc,
+    thread,
+    time::Duration,
+};
+
+// A single-threaded executor for reproducing concurrency bugs. The next task
+// to poll is picked at random out of the ready ones, from an RNG seeded with
+// `seed`, and `TimerFuture`s run on a virtual clock that jumps straight to
+// the next deadline once no task is ready. Same seed, same interleaving, as
+// long as the tasks are only woken from inside the simulation.
+// A test that panics while its `Simulation` is alive prints the seed.
+pub struct Simulation {
+    seed: u64,
+    rng: RefCell<StdRng>,
+    executor: LocalExecutor,
+    clock: Rc<Clock>,
+}
+
+struct Clock {
+    // Virtual time since the simulation started.
+    now: Cell<Duration>,
+    // Deadlines and ids, earliest first. Timers with the same deadline fire
+    // in the order they were added. Cancelled ones stay in here until they
+    // come up, but have no entry in `fires`.
+    timers: RefCell<BinaryHeap<Reverse<(Duration, u64)>>>,
+    fires: RefCell<HashMap<u64, Box<dyn FnOnce()>>>,
+}
+
+thread_local! {
+    // Timers created on this thread go to this clock, not to a real thread.
+    static CURRENT: RefCell<Option<Rc<Clock>>> = const { RefCell::new(None) };
+    // Counts across simulations, so a timer left over from an earlier one
+    // never cancels a timer of the current one.
+    static NEXT_TIMER_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+// Fire `fire` after `after` of virtual time, if there is a simulation on
+// this thread. Hands `fire` back otherwise. The id is for
+// `cancel_virtual_timer`, and `None` for a timer that never fires.
+pub(crate) fn add_virtual_timer<F>(after: Duration, fire: F) -> Result<Option<u64>, F>
+where
+    F: FnOnce() + 'static,
+{
+    CURRENT.with(|c| match &*c.borrow() {
+        Some(clock) => {
+            // Too far out to ever come up.
+            let Some(deadline) = clock.now.get().checked_add(after) else {
+                return Ok(None);
+            };
+            let id = NEXT_TIMER_ID.with(|n| n.replace(n.get() + 1));
+            clock.timers.borrow_mut().push(Reverse((deadline, id)));
+            clock.fires.borrow_mut().insert(id, Box::new(fire));
+            Ok(Some(id))
+        }
+        None => Err(fire),
+    })
+}
+
+// Drop a virtual timer that has not fired yet, so the clock does not jump
+// to its deadline.
+pub(crate) fn cancel_virtual_timer(id: u64) {
+    let clock = CURRENT.with(|c| c.borrow().clone());
+    // The simulation is gone, and its timers with it.
+    let Some(clock) = clock else {
+        return;
+    };
+    let fire = clock.fires.borrow_mut().remove(&id);
+    let mut timers = clock.timers.borrow_mut();
+    if timers.len() > 2 * clock.fires.borrow().len() {
+        let fires = clock.fires.borrow();
+        timers.retain(|Reverse((_, id))| fires.contains_key(id));
+    }
+    drop(timers);
+    // Dropped outside the borrows, like it is fired.
+    drop(fire);
+}
+
+impl Simulation {
+    // Only one simulation can exist on a thread at a time.
+    pub fn new(seed: u64) -> Self {
+        let clock = Rc::new(Clock {
+            now: Cell::new(Duration::ZERO),
+            timers: RefCell::default(),
+            fires: RefCell::default(),
+        });
+        CURRENT.with(|c| {
+            let mut current = c.borrow_mut();
+            assert!(
+                current.is_none(),
+                "a Simulation already exists on this thread"
+            );
+            *current = Some(clock.clone());
+        });
+        Simulation {
+            seed,
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+            executor: LocalExecutor::new(),
+            clock,
+        }
+    }
+
+    // Seeded from `SIM_SEED` to replay a failure, or at random.
+    pub fn from_env() -> Self {
+        let seed = std::env::var("SIM_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+        Self::new(seed)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Virtual time since the simulation started.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now.get()
+    }
+
+    // `spawn_local` works too, from inside the simulation's tasks.
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        self.executor.spawn_local(future)
+    }
+
+    // Run until every task has finished, or until no task can make progress
+    // any more, neither now nor at a later virtual time.
+    pub fn run(&self) {
+        let inner = &self.executor.inner;
+        self.executor.enter(|| {
+            let mut ready = Vec::new();
+            loop {
+                ready.extend(std::iter::from_fn(|| inner.ready_queue.tasks.pop()));
+                if ready.is_empty() {
+                    if self.clock.advance() {
+                        continue;
+                    }
+                    inner.drop_unwakeable();
+                    if inner.ready_queue.tasks.is_empty() {
+                        return;
+                    }
+                    continue;
+                }
+                let index = self.rng.borrow_mut().random_range(0..ready.len());
+                inner.poll(ready.swap_remove(index));
+            }
+        });
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.borrow_mut().take());
+        if thread::panicking() {
+            eprintln!(
+                "simulation failed with seed {0}, replay with SIM_SEED={0}",
+                self.seed
+            );
+        }
+    }
+}
+
+impl Clock {
+    // Jump to the earliest deadline and fire its timer, passing over
+    // cancelled ones without moving the clock.
+    // Returns false if there are no timers left.
+    fn advance(&self) -> bool {
+        loop {
+            let Some(Reverse((deadline, id))) = self.timers.borrow_mut().pop() else {
+                return false;
+            };
+            let Some(fire) = self.fires.borrow_mut().remove(&id) else {
+                continue;
+            };
+            self.now.set(deadline);
+            fire();
+            return true;
+        }
+    }
+}
+
+#[test]
+fn test_simulation() {
+    use super::{spawn_local, yield_now};
+    use crate::simple_future::TimerFuture;
+    use std::time::Instant;
+
+    fn interleaving(seed: u64) -> (Vec<(usize, usize)>, Duration) {
+        let sim = Simulation::new(seed);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        for task in 0..5 {
+            let log = log.clone();
+            sim.spawn_local(async move {
+                for step in 0..3 {
+                    log.borrow_mut().push((task, step));
+                    TimerFuture::new(Duration::from_secs(task as u64 % 3)).await;
+                    yield_now().await;
+                }
+                // A long sleep costs no real time.
+                spawn_local(TimerFuture::new(Duration::from_secs(3600)))
+                    .await
+                    .unwrap();
+            });
+        }
+        sim.run();
+        let log = log.borrow().clone();
+        (log, sim.elapsed())
+    }
+
+    let start = Instant::now();
+    let (first, elapsed) = interleaving(42);
+    assert_eq!(first.len(), 15);
+    assert_eq!(elapsed, Duration::from_secs(6 + 3600));
+    assert_eq!(interleaving(42), (first, elapsed));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // A timer too far out to ever fire does not stop the run.
+    let sim = Simulation::new(0);
+    let never = sim.spawn_local(async {
+        TimerFuture::new(Duration::from_secs(1)).await;
+        TimerFuture::new(Duration::MAX).await;
+    });
+    sim.run();
+    assert_eq!(sim.elapsed(), Duration::from_secs(1));
+    assert!(!never.is_finished());
+    drop(sim);
+
+    // A dropped timer does not move the clock to its deadline.
+    let sim = Simulation::new(0);
+    sim.spawn_local(async {
+        drop(TimerFuture::new(Duration::from_secs(3600)));
+        TimerFuture::new(Duration::from_secs(1)).await;
+    });
+    sim.run();
+    assert_eq!(sim.elapsed(), Duration::from_secs(1));
+}
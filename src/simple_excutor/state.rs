@@ -0,0 +1,98 @@
+use super::TaskState;
+use std::sync::atomic::{AtomicU8, Ordering::*};
+
+// Not queued and not running, waiting for a wake.
+const IDLE: u8 = 0;
+// In a ready queue. A queued task is never queued a second time.
+const SCHEDULED: u8 = 1;
+// Being polled by a worker.
+const RUNNING: u8 = 2;
+// Woken while being polled, the worker queues it again once `poll` returns.
+const NOTIFIED: u8 = 3;
+// Finished or cancelled. The future is gone.
+const COMPLETE: u8 = 4;
+
+// Scheduling state of a task. Whoever moves a task into `RUNNING` owns its
+// future until it moves the task out again.
+pub(super) struct State(AtomicU8);
+
+impl State {
+    // Tasks start out on their way into the ready queue.
+    pub(super) fn new() -> Self {
+        Self(AtomicU8::new(SCHEDULED))
+    }
+
+    // Returns true if the caller has to put the task into a ready queue.
+    pub(super) fn wake(&self) -> bool {
+        let mut state = self.0.load(Acquire);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return false,
+            };
+            match self.0.compare_exchange_weak(state, next, AcqRel, Acquire) {
+                Ok(_) => return state == IDLE,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    // Called by a worker that popped the task from a ready queue.
+    // Fails if the task was cancelled while it was queued.
+    pub(super) fn start_running(&self) -> bool {
+        self.0
+            .compare_exchange(SCHEDULED, RUNNING, AcqRel, Acquire)
+            .is_ok()
+    }
+
+    // Take ownership of the future of a task that is not running, to drop it.
+    // Returns false if the task is running, or already complete.
+    pub(super) fn claim(&self) -> bool {
+        let mut state = self.0.load(Acquire);
+        loop {
+            if state != IDLE && state != SCHEDULED {
+                return false;
+            }
+            match self
+                .0
+                .compare_exchange_weak(state, RUNNING, AcqRel, Acquire)
+            {
+                Ok(_) => return true,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    pub(super) fn get(&self) -> TaskState {
+        match self.0.load(Acquire) {
+            IDLE => TaskState::Idle,
+            SCHEDULED => TaskState::Queued,
+            RUNNING | NOTIFIED => TaskState::Running,
+            _ => TaskState::Finished,
+        }
+    }
+
+    pub(super) fn is_running(&self) -> bool {
+        matches!(self.0.load(Acquire), RUNNING | NOTIFIED)
+    }
+
+    // Called after `poll` returned `Pending`. Returns true if the task was
+    // woken in the meantime, it is then scheduled again and the caller has to
+    // put it into a ready queue.
+    pub(super) fn finish_poll(&self) -> bool {
+        match self.0.compare_exchange(RUNNING, IDLE, AcqRel, Acquire) {
+            Ok(_) => false,
+            Err(state) => {
+                debug_assert_eq!(state, NOTIFIED);
+                // Wakes are no-ops while `NOTIFIED`, so a plain store is fine.
+                self.0.store(SCHEDULED, Release);
+                true
+            }
+        }
+    }
+
+    pub(super) fn complete(&self) {
+        self.0.store(COMPLETE, Release);
+    }
+}
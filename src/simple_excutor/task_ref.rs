@@ -0,0 +1,303 @@
+use super::{join_handle::Panicked, Task};
+use std::{
+    cell::UnsafeCell,
+    future::Future,
+    mem::ManuallyDrop,
+    ops::Deref,
+    pin::Pin,
+    ptr::{self, NonNull},
+    sync::atomic::{
+        fence, AtomicUsize,
+        Ordering::{Acquire, Relaxed, Release},
+    },
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+// A hand-rolled `Arc<Task>`, so that a `Waker` can be a plain pointer to it.
+// Same scheme as the `weak` Arc in `bin/chapter6.rs`.
+pub(super) struct TaskRef {
+    ptr: NonNull<Header>,
+}
+
+// Does not keep the task alive, see `upgrade`.
+pub(super) struct WeakTaskRef {
+    ptr: NonNull<Header>,
+}
+
+// A task and its future, in a single allocation.
+#[repr(C)]
+struct TaskCell<F> {
+    // First, so a pointer to the cell is one to the header.
+    header: Header,
+    // Only touched by whoever moved the task's state into `RUNNING`.
+    future: UnsafeCell<Option<F>>,
+}
+
+// The part of a `TaskCell` that does not depend on the future's type.
+struct Header {
+    // Number of `TaskRef`s, wakers included.
+    strong: AtomicUsize,
+    // Number of `WeakTaskRef`s, plus one if there are any `TaskRef`s.
+    weak: AtomicUsize,
+    vtable: &'static Vtable,
+    // Dropped once `strong` reaches zero, the allocation once `weak` does.
+    task: ManuallyDrop<Task>,
+}
+
+type TaskPoll = Poll<Result<(), Panicked>>;
+
+// What to do with the future of a `TaskCell` whose type is lost.
+struct Vtable {
+    has_future: unsafe fn(NonNull<Header>) -> bool,
+    // Drops the future once it is ready.
+    poll: unsafe fn(NonNull<Header>, &mut Context<'_>) -> TaskPoll,
+    // Drops the future, or just forgets it if `leak`. False if it was gone.
+    drop_future: unsafe fn(NonNull<Header>, bool) -> bool,
+    dealloc: unsafe fn(NonNull<Header>),
+}
+
+// Safety: the future is `Send`, and only one thread at a time touches it,
+// see `TaskCell::future`.
+unsafe impl Send for TaskRef {}
+unsafe impl Sync for TaskRef {}
+unsafe impl Send for WeakTaskRef {}
+unsafe impl Sync for WeakTaskRef {}
+
+impl TaskRef {
+    // Safety: the future must be dropped before anything it borrows goes
+    // away.
+    pub(super) unsafe fn new<F>(task: Task, future: F) -> Self
+    where
+        F: Future<Output = Result<(), Panicked>> + Send,
+    {
+        let cell = Box::new(TaskCell {
+            header: Header {
+                strong: AtomicUsize::new(1),
+                weak: AtomicUsize::new(1),
+                vtable: &Vtable {
+                    has_future: has_future::<F>,
+                    poll: poll::<F>,
+                    drop_future: drop_future::<F>,
+                    dealloc: dealloc::<F>,
+                },
+                task: ManuallyDrop::new(task),
+            },
+            future: UnsafeCell::new(Some(future)),
+        });
+        TaskRef {
+            ptr: NonNull::from(Box::leak(cell)).cast(),
+        }
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    // Safety for the future accessors: the caller moved the task into
+    // `RUNNING`.
+    pub(super) unsafe fn has_future(&self) -> bool {
+        (self.header().vtable.has_future)(self.ptr)
+    }
+
+    pub(super) unsafe fn poll_future(&self, cx: &mut Context<'_>) -> TaskPoll {
+        (self.header().vtable.poll)(self.ptr, cx)
+    }
+
+    pub(super) unsafe fn drop_future(&self) -> bool {
+        (self.header().vtable.drop_future)(self.ptr, false)
+    }
+
+    // For a future that panicked while being dropped.
+    pub(super) unsafe fn leak_future(&self) {
+        (self.header().vtable.drop_future)(self.ptr, true);
+    }
+
+    pub(super) fn downgrade(&self) -> WeakTaskRef {
+        if self.header().weak.fetch_add(1, Relaxed) > usize::MAX / 2 {
+            std::process::abort();
+        }
+        WeakTaskRef { ptr: self.ptr }
+    }
+
+    // Borrow the task as a `Waker`, without touching the reference count.
+    pub(super) fn waker_ref(&self) -> ManuallyDrop<Waker> {
+        let raw = RawWaker::new(self.ptr.as_ptr() as *const (), &VTABLE);
+        ManuallyDrop::new(unsafe { Waker::from_raw(raw) })
+    }
+
+    // Safety: `ptr` must come from a `TaskRef` whose count we take over.
+    unsafe fn from_raw(ptr: *const ()) -> Self {
+        TaskRef {
+            ptr: NonNull::new_unchecked(ptr as *mut Header),
+        }
+    }
+}
+
+impl Deref for TaskRef {
+    type Target = Task;
+
+    fn deref(&self) -> &Task {
+        &self.header().task
+    }
+}
+
+impl Clone for TaskRef {
+    fn clone(&self) -> Self {
+        if self.header().strong.fetch_add(1, Relaxed) > usize::MAX / 2 {
+            std::process::abort();
+        }
+        TaskRef { ptr: self.ptr }
+    }
+}
+
+impl Drop for TaskRef {
+    fn drop(&mut self) {
+        if self.header().strong.fetch_sub(1, Release) == 1 {
+            fence(Acquire);
+            // Safety: the strong count is zero, so nothing will access the
+            // task any more.
+            // Nobody holds a waker for the task any more, so it can never
+            // finish.
+            unsafe { self.drop_future_and_finish() };
+            // Only take `&mut` of the task, weak refs may still be looking
+            // at the counters.
+            unsafe {
+                ManuallyDrop::drop(&mut *ptr::addr_of_mut!((*self.ptr.as_ptr()).task));
+            }
+            // Give up the weak count that all the strong ones shared.
+            drop(WeakTaskRef { ptr: self.ptr });
+        }
+    }
+}
+
+impl WeakTaskRef {
+    pub(super) fn upgrade(&self) -> Option<TaskRef> {
+        let strong = &self.header().strong;
+        let mut count = strong.load(Relaxed);
+        loop {
+            if count == 0 {
+                return None;
+            }
+            assert!(count <= usize::MAX / 2);
+            match strong.compare_exchange_weak(count, count + 1, Relaxed, Relaxed) {
+                Ok(_) => return Some(TaskRef { ptr: self.ptr }),
+                Err(actual) => count = actual,
+            }
+        }
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl Clone for WeakTaskRef {
+    fn clone(&self) -> Self {
+        if self.header().weak.fetch_add(1, Relaxed) > usize::MAX / 2 {
+            std::process::abort();
+        }
+        WeakTaskRef { ptr: self.ptr }
+    }
+}
+
+impl Drop for WeakTaskRef {
+    fn drop(&mut self) {
+        if self.header().weak.fetch_sub(1, Release) == 1 {
+            fence(Acquire);
+            // The task itself is already gone, `ManuallyDrop` keeps this from
+            // dropping it again.
+            unsafe { (self.header().vtable.dealloc)(self.ptr) };
+        }
+    }
+}
+
+// The future slot of the cell `header` is at the front of.
+unsafe fn future_slot<'a, F>(header: NonNull<Header>) -> &'a mut Option<F> {
+    let cell = header.cast::<TaskCell<F>>().as_ptr();
+    &mut *UnsafeCell::raw_get(ptr::addr_of!((*cell).future))
+}
+
+unsafe fn has_future<F>(header: NonNull<Header>) -> bool {
+    future_slot::<F>(header).is_some()
+}
+
+unsafe fn poll<F>(header: NonNull<Header>, cx: &mut Context<'_>) -> TaskPoll
+where
+    F: Future<Output = Result<(), Panicked>>,
+{
+    let slot = future_slot::<F>(header);
+    // Safety: the future never moves out of its cell before it is dropped.
+    let future = Pin::new_unchecked(slot.as_mut().expect("task has no future"));
+    let poll = future.poll(cx);
+    if poll.is_ready() {
+        *slot = None;
+    }
+    poll
+}
+
+unsafe fn drop_future<F>(header: NonNull<Header>, leak: bool) -> bool {
+    let slot = future_slot::<F>(header);
+    if slot.is_none() {
+        return false;
+    }
+    match leak {
+        true => ptr::write(slot, None),
+        false => *slot = None,
+    }
+    true
+}
+
+unsafe fn dealloc<F>(header: NonNull<Header>) {
+    // The future is gone already, this only frees the memory.
+    drop(Box::from_raw(header.cast::<TaskCell<F>>().as_ptr()));
+}
+
+// A waker owns one strong count of the task it points at.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(ptr: *const ()) -> RawWaker {
+    let task = ManuallyDrop::new(TaskRef::from_raw(ptr));
+    std::mem::forget(TaskRef::clone(&task));
+    RawWaker::new(ptr, &VTABLE)
+}
+
+// Hands the waker's own count to the ready queue, no clone needed.
+unsafe fn wake(ptr: *const ()) {
+    TaskRef::from_raw(ptr).wake();
+}
+
+unsafe fn wake_by_ref(ptr: *const ()) {
+    ManuallyDrop::new(TaskRef::from_raw(ptr)).wake_by_ref();
+}
+
+unsafe fn drop_waker(ptr: *const ()) {
+    drop(TaskRef::from_raw(ptr));
+}
+
+#[test]
+fn test_waker_refcount() {
+    use super::new_executor_and_spawner;
+    use std::{future::poll_fn, task::Poll};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let mut polled = false;
+    let handle = spawner.spawn(poll_fn(move |cx| {
+        if polled {
+            return Poll::Ready(());
+        }
+        polled = true;
+        let clones = [cx.waker().clone(), cx.waker().clone()];
+        let [first, second] = clones;
+        drop(first);
+        // Woken by value from another thread.
+        std::thread::spawn(move || second.wake()).join().unwrap();
+        Poll::Pending
+    }));
+    let tasks = executor.shared.clone();
+    drop(spawner);
+
+    // Returns only if every count the wakers took was given back.
+    executor.run();
+    assert!(handle.join().is_ok());
+    assert_eq!(tasks.live_tasks(), 0);
+}
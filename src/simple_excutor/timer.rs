@@ -0,0 +1,168 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{Condvar, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+// The one thread that fires the timers created outside of a `Simulation`,
+// started along with the first of them. It sleeps until the earliest
+// deadline, or until a timer with an earlier one comes in.
+struct Driver {
+    timers: Mutex<Timers>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct Timers {
+    // Deadline and id of every timer, earliest first. Ties go by id, so in
+    // the order the timers were added. A cancelled timer stays in here
+    // until it comes up, or until cancelled ones make up half the heap.
+    heap: BinaryHeap<Reverse<(Instant, u64)>>,
+    // The timers that have neither fired nor been cancelled, by id.
+    fires: HashMap<u64, Box<dyn FnOnce() + Send>>,
+    next_timer_id: u64,
+}
+
+// Fire `fire` on the driver thread once `after` has passed. Returns the id
+// to cancel it with, or `None` if `after` is too far out to ever pass.
+pub(crate) fn add_timer<F>(after: Duration, fire: F) -> Option<u64>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let deadline = Instant::now().checked_add(after)?;
+    let driver = driver();
+    let mut timers = driver.timers.lock().unwrap();
+    let id = timers.next_timer_id;
+    timers.next_timer_id += 1;
+    timers.heap.push(Reverse((deadline, id)));
+    timers.fires.insert(id, Box::new(fire));
+    // The driver sleeps until the old earliest deadline, which is too late.
+    if timers.heap.peek() == Some(&Reverse((deadline, id))) {
+        driver.condvar.notify_one();
+    }
+    Some(id)
+}
+
+// Drop a timer that has not fired yet.
+pub(crate) fn cancel_timer(id: u64) {
+    let mut timers = driver().timers.lock().unwrap();
+    let fire = timers.fires.remove(&id);
+    if timers.heap.len() > 2 * timers.fires.len() {
+        let Timers { heap, fires, .. } = &mut *timers;
+        heap.retain(|Reverse((_, id))| fires.contains_key(id));
+    }
+    // Dropped outside the lock, like it is fired.
+    drop(timers);
+    drop(fire);
+}
+
+fn driver() -> &'static Driver {
+    static DRIVER: OnceLock<Driver> = OnceLock::new();
+    DRIVER.get_or_init(|| {
+        // Waits in `driver` until we are done here.
+        thread::Builder::new()
+            .name("excutor-timer".into())
+            .spawn(|| driver().run())
+            .expect("failed to start the timer thread");
+        Driver {
+            timers: Mutex::default(),
+            condvar: Condvar::new(),
+        }
+    })
+}
+
+impl Driver {
+    fn run(&self) {
+        let mut timers = self.timers.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            match timers.heap.peek() {
+                Some(&Reverse((deadline, id))) if deadline <= now => {
+                    timers.heap.pop();
+                    let Some(fire) = timers.fires.remove(&id) else {
+                        // Cancelled.
+                        continue;
+                    };
+                    // Fired outside the lock, waking a task may add a timer.
+                    drop(timers);
+                    // Wakers are arbitrary code. One that panics must not
+                    // take every other timer down with this thread.
+                    let _ = catch_unwind(AssertUnwindSafe(fire));
+                    timers = self.timers.lock().unwrap();
+                }
+                Some(&Reverse((deadline, _))) => {
+                    timers = self.condvar.wait_timeout(timers, deadline - now).unwrap().0;
+                }
+                None => timers = self.condvar.wait(timers).unwrap(),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_timer_driver() {
+    use super::{block_on, ExecutorBuilder, Priority};
+    use crate::simple_future::TimerFuture;
+    use futures::future::join_all;
+    use std::sync::{mpsc, Arc};
+
+    // In deadline order, all on the one thread.
+    let (fired, order) = mpsc::channel();
+    for ms in [30, 10, 20] {
+        let fired = fired.clone();
+        add_timer(Duration::from_millis(ms), move || {
+            let thread = thread::current().name().map(str::to_owned);
+            fired.send((ms, thread)).unwrap();
+        });
+    }
+    drop(fired);
+    let order: Vec<_> = order.iter().collect();
+    let timer_thread = Some("excutor-timer".to_owned());
+    assert_eq!(order, [10, 20, 30].map(|ms| (ms, timer_thread.clone())));
+
+    // Neither a timer that never fires nor one that panics gets in the way
+    // of the ones after it.
+    assert_eq!(add_timer(Duration::MAX, || {}), None);
+    drop(TimerFuture::new(Duration::MAX));
+    add_timer(Duration::ZERO, || panic!("boom"));
+
+    // Cancelled timers let go of what they hold right away.
+    let held = Arc::new(());
+    let ids: Vec<_> = (0..1000)
+        .map(|_| {
+            let held = held.clone();
+            add_timer(Duration::from_secs(60), move || drop(held)).unwrap()
+        })
+        .collect();
+    ids.into_iter().for_each(cancel_timer);
+    assert_eq!(Arc::strong_count(&held), 1);
+
+    // Waking a task of an executor whose queue is full does not hold up
+    // the timers after it.
+    let (executor, spawner) = ExecutorBuilder::new().capacity(1).build();
+    spawner.spawn(TimerFuture::new(Duration::from_millis(10)));
+    executor.shared.injectors[Priority::Normal.index()]
+        .pop()
+        .unwrap()
+        .run();
+    spawner.spawn(async {});
+    block_on(TimerFuture::new(Duration::from_millis(50)));
+    drop(spawner);
+    executor.run();
+
+    // Ten thousand sleeps at once, without a thread each.
+    let start = Instant::now();
+    let woken = Arc::new(Mutex::new(0));
+    block_on(join_all((0..10_000).map(|i| {
+        let woken = woken.clone();
+        async move {
+            TimerFuture::new(Duration::from_millis(i % 50)).await;
+            *woken.lock().unwrap() += 1;
+        }
+    })));
+    assert_eq!(*woken.lock().unwrap(), 10_000);
+    assert!(start.elapsed() >= Duration::from_millis(49));
+}
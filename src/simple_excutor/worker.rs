@@ -0,0 +1,307 @@
+use super::{
+    priority::{Aging, Priority},
+    Shared, TaskRef,
+};
+use std::{
+    array,
+    cell::Cell,
+    collections::VecDeque,
+    ptr,
+    sync::{atomic::Ordering, Arc, Mutex, PoisonError},
+    time::Instant,
+};
+
+// The workers of one `Executor::run_with_workers` or `Executor::shutdown` call.
+pub(super) struct Workers {
+    shared: Arc<Shared>,
+    local_queues: Vec<Mutex<LocalQueue>>,
+    // Set while shutting down: keep going until no task is left, or until
+    // the deadline passes.
+    drain_deadline: Option<Instant>,
+}
+
+// How many tasks in a row may come out of a worker's `next` slot before
+// it looks at its queues again.
+const NEXT_TASK_LIMIT: usize = 3;
+
+#[derive(Default)]
+struct LocalQueue {
+    // The task woken last by the running one, say the receiving end of a
+    // channel. Runs right after it instead of at the back of the queue.
+    next: Option<TaskRef>,
+    // Tasks taken from `next` in a row.
+    next_streak: usize,
+    tasks: [VecDeque<TaskRef>; Priority::COUNT],
+    // Only touched by the worker owning the queue.
+    aging: Aging,
+}
+
+#[derive(Clone, Copy)]
+struct Current {
+    workers: *const Workers,
+    index: usize,
+}
+
+// Sets `CURRENT` back to what it was once dropped, so also when a panic
+// unwinds out of `Workers::run`.
+struct Restore(Current);
+
+thread_local! {
+    static CURRENT: Cell<Current> = const {
+        Cell::new(Current {
+            workers: ptr::null(),
+            index: 0,
+        })
+    };
+}
+
+// Put a woken task into the `next` slot of the worker running on this
+// thread, moving the one there to the back of the local queue. Hands the
+// task back if this thread is not one of its workers, or if some worker is
+// idle and should rather get it from the injector.
+pub(super) fn push_local_if_busy(task: TaskRef) -> Result<(), TaskRef> {
+    push(task, false)
+}
+
+// Like `push_local_if_busy`, but also when other workers are idle, and
+// onto the back of the local queue. Used when the injector is full.
+pub(super) fn push_local(task: TaskRef) -> Result<(), TaskRef> {
+    push(task, true)
+}
+
+fn push(task: TaskRef, even_if_idle: bool) -> Result<(), TaskRef> {
+    let current = CURRENT.with(Cell::get);
+    if current.workers.is_null() {
+        return Err(task);
+    }
+    // Safety: `CURRENT` is only set while `Workers::run` is on the stack.
+    let workers = unsafe { &*current.workers };
+    if !Arc::ptr_eq(&workers.shared, &task.spawner.shared)
+        || (!even_if_idle && workers.shared.parker.idle() > 0)
+    {
+        return Err(task);
+    }
+    let mut local = workers.local_queues[current.index].lock().unwrap();
+    let task = match even_if_idle {
+        true => task,
+        false => match local.next.replace(task) {
+            Some(task) => task,
+            None => return Ok(()),
+        },
+    };
+    local.tasks[task.priority.index()].push_back(task);
+    Ok(())
+}
+
+impl Workers {
+    pub(super) fn new(
+        shared: Arc<Shared>,
+        num_workers: usize,
+        drain_deadline: Option<Instant>,
+    ) -> Self {
+        Workers {
+            shared,
+            local_queues: (0..num_workers).map(|_| Mutex::default()).collect(),
+            drain_deadline,
+        }
+    }
+
+    pub(super) fn run(&self, index: usize) {
+        let _restore = Restore(CURRENT.with(|c| {
+            c.replace(Current {
+                workers: self,
+                index,
+            })
+        }));
+        while let Some(task) = self.next_task(index).or_else(|| self.park(index)) {
+            task.run();
+            if self
+                .drain_deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                break;
+            }
+        }
+    }
+
+    fn next_task(&self, index: usize) -> Option<TaskRef> {
+        let mut local = self.local_queues[index].lock().unwrap();
+        let next_class = local.next.as_ref().map(|task| task.priority.index());
+        let ready = array::from_fn(|class| {
+            next_class == Some(class)
+                || !local.tasks[class].is_empty()
+                || !self.shared.injectors[class].is_empty()
+        });
+        let picked = local.aging.pick(ready);
+        // Another worker may have emptied the injector since, so fall back to
+        // any class.
+        let task = picked
+            .into_iter()
+            .chain(0..Priority::COUNT)
+            .find_map(|class| {
+                if next_class == Some(class) && local.next_streak < NEXT_TASK_LIMIT {
+                    local.next_streak += 1;
+                    return local.next.take();
+                }
+                let task = local.tasks[class]
+                    .pop_front()
+                    .or_else(|| self.shared.injectors[class].pop())?;
+                local.next_streak = 0;
+                Some(task)
+            })
+            // Over the limit, but nothing else to run.
+            .or_else(|| local.next.take());
+        drop(local);
+        task.or_else(|| self.steal(index))
+    }
+
+    // Take half of the highest class of tasks in another worker's local queue.
+    fn steal(&self, index: usize) -> Option<TaskRef> {
+        let num_workers = self.local_queues.len();
+        if num_workers == 1 {
+            return None;
+        }
+        let start = rand::random_range(0..num_workers);
+        for victim in (start..start + num_workers).map(|i| i % num_workers) {
+            if victim == index {
+                continue;
+            }
+            let mut stolen = {
+                let mut victim_queue = self.local_queues[victim].lock().unwrap();
+                let Some(tasks) = victim_queue.tasks.iter_mut().find(|t| !t.is_empty()) else {
+                    continue;
+                };
+                let count = tasks.len().div_ceil(2);
+                tasks.drain(..count).collect::<VecDeque<_>>()
+            };
+            if let Some(task) = stolen.pop_front() {
+                let mut local = self.local_queues[index].lock().unwrap();
+                local.tasks[task.priority.index()].extend(stolen);
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    // Search for a task until there is one, sleeping in between. Returns
+    // `None` once all spawners and tasks are gone, or on shutdown, or once
+    // there is nothing left to do while draining.
+    fn park(&self, index: usize) -> Option<TaskRef> {
+        let parker = &self.shared.parker;
+        parker.start_searching();
+        loop {
+            if let Some(task) = self.next_task(index) {
+                parker.found_task();
+                return Some(task);
+            }
+            if self.is_done() {
+                parker.stop_searching();
+                return None;
+            }
+            let epoch = parker.prepare_sleep();
+            // Whatever got pushed before `prepare_sleep` came without a wake.
+            if self.has_tasks() || self.is_done() {
+                parker.cancel_sleep();
+                continue;
+            }
+            if !parker.sleep(epoch, self.drain_deadline) {
+                return None;
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        match self.drain_deadline {
+            Some(_) => self.shared.live_tasks() == 0,
+            None => {
+                self.shared.closed.load(Ordering::Acquire)
+                    || self.shared.spawners.load(Ordering::Acquire) == 0
+            }
+        }
+    }
+
+    // Any task this worker could get at, without taking it.
+    fn has_tasks(&self) -> bool {
+        self.shared.injectors.iter().any(|queue| !queue.is_empty())
+            || self.local_queues.iter().any(|local| {
+                let local = local.lock().unwrap();
+                local.tasks.iter().any(|tasks| !tasks.is_empty())
+            })
+    }
+}
+
+impl Drop for Workers {
+    // A shutdown that ran out of time leaves tasks in the local queues.
+    // They are dropped with us, and are no longer queued.
+    fn drop(&mut self) {
+        for local in &mut self.local_queues {
+            let local = local.get_mut().unwrap_or_else(PoisonError::into_inner);
+            let tasks = local.tasks.iter_mut().flat_map(|tasks| tasks.drain(..));
+            for task in local.next.take().into_iter().chain(tasks) {
+                self.shared.metrics.dequeued();
+                drop(task);
+            }
+        }
+    }
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.set(self.0));
+    }
+}
+
+#[test]
+fn test_next_task_slot() {
+    use super::new_executor_and_spawner;
+    use futures::{channel::mpsc, SinkExt, StreamExt};
+
+    let (executor, spawner) = new_executor_and_spawner();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut ping_sender, mut pings) = mpsc::channel(1);
+    let (mut pong_sender, mut pongs) = mpsc::channel(1);
+    let ping_log = log.clone();
+    spawner.spawn(async move {
+        for _ in 0..10 {
+            ping_log.lock().unwrap().push("ping");
+            ping_sender.send(()).await.unwrap();
+            pongs.next().await.unwrap();
+        }
+    });
+    spawner.spawn(async move {
+        while pings.next().await.is_some() {
+            pong_sender.send(()).await.unwrap();
+        }
+    });
+    let filler_log = log.clone();
+    spawner.spawn(async move { filler_log.lock().unwrap().push("filler") });
+    drop(spawner);
+    executor.run_with_workers(1);
+
+    // The pair bounces through the `next` slot until it hits the limit,
+    // then the queued task gets its turn.
+    let log = log.lock().unwrap();
+    assert_eq!(log.len(), 11);
+    assert_eq!(log[..4], ["ping", "ping", "ping", "filler"]);
+}
+
+#[test]
+fn test_current_after_panic() {
+    use super::{ExecutorBuilder, TaskHooks, TaskInfo};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    struct Panicking;
+    impl TaskHooks for Panicking {
+        fn before_poll(&self, _task: &TaskInfo<'_>) {
+            panic!("boom");
+        }
+    }
+
+    let (executor, spawner) = ExecutorBuilder::new().hooks(Panicking).build();
+    spawner.spawn(async {});
+    drop(spawner);
+    let run = catch_unwind(AssertUnwindSafe(|| executor.run()));
+    assert!(run.is_err());
+    // No longer pointing at the workers that are gone.
+    assert!(CURRENT.with(Cell::get).workers.is_null());
+}
@@ -1,4 +1,6 @@
-use futures::task::AtomicWaker;
+use crate::simple_excutor::{
+    add_timer, add_virtual_timer, cancel_timer, cancel_virtual_timer, poll_proceed, AtomicWaker,
+};
 use std::{
     future::Future,
     pin::Pin,
@@ -6,14 +8,20 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    task::{Context, Poll},
-    thread,
+    task::{ready, Context, Poll},
     time::Duration,
 };
 
 // a simple leaf future
 pub struct TimerFuture {
     shared_state: Arc<SharedState>,
+    // where the timer went, to cancel it on drop.
+    timer: Option<TimerId>,
+}
+
+enum TimerId {
+    Thread(u64),
+    Virtual(u64),
 }
 
 struct SharedState {
@@ -24,6 +32,9 @@ struct SharedState {
 impl Future for TimerFuture {
     type Output = ();
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // yield instead if the task has used up its budget.
+        ready!(poll_proceed(cx));
+
         // quick check to avoid registration if already done.
         if self.shared_state.completed.load(Ordering::Relaxed) {
             return Poll::Ready(());
@@ -47,12 +58,34 @@ impl TimerFuture {
             completed: AtomicBool::new(false),
             waker: AtomicWaker::new(),
         });
-        let thread_shared_state = shared_state.clone();
-        thread::spawn(move || {
-            thread::sleep(duration);
-            thread_shared_state.completed.store(true, Ordering::Relaxed);
-            thread_shared_state.waker.wake();
-        });
-        TimerFuture { shared_state }
+        // only a weak reference, so a dropped timer lets go of its waker.
+        let thread_shared_state = Arc::downgrade(&shared_state);
+        let fire = move || {
+            if let Some(shared_state) = thread_shared_state.upgrade() {
+                shared_state.completed.store(true, Ordering::Relaxed);
+                shared_state.waker.wake();
+            }
+        };
+        // inside a `Simulation` the timer runs on its virtual clock, and
+        // on the shared timer thread otherwise.
+        let timer = match add_virtual_timer(duration, fire) {
+            Ok(id) => id.map(TimerId::Virtual),
+            Err(fire) => add_timer(duration, fire).map(TimerId::Thread),
+        };
+        TimerFuture {
+            shared_state,
+            timer,
+        }
+    }
+}
+
+impl Drop for TimerFuture {
+    fn drop(&mut self) {
+        // don't leave a dropped timer around until it is due.
+        match self.timer {
+            Some(TimerId::Thread(id)) => cancel_timer(id),
+            Some(TimerId::Virtual(id)) => cancel_virtual_timer(id),
+            None => {}
+        }
     }
 }